@@ -0,0 +1,449 @@
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use anyhow::{anyhow, Context};
+
+use crate::{
+    data::{
+        levels,
+        world::{
+            bnys_loader::{self, LevelInfo},
+            LevelError, LevelId, World,
+        },
+        LevelState,
+    },
+    serve,
+    solver::{
+        self, analysis,
+        planner::{self, Step},
+        Search,
+    },
+    tui,
+};
+
+const USAGE: &str = "\
+Usage: mech-bun [--burrows <dir>] <command>
+
+Commands:
+  play <world> [<burrow> <depth>]  play a world, optionally starting at a level;
+                                   save with ctrl+s and load with ctrl+o
+  solve <world> <burrow> <depth>   print the shortest solution of a level
+  replay <world> <burrow> <depth>  solve a level and replay the solution
+  plan <world>                     plan how to catch every bun of a world
+  validate <dir>                   check that every level of the worlds in <dir> parses
+  render <level>                   print a `.level` file
+  analyze <level>                  show where Paquerette and the buns of a `.level` file can get to
+  edit <level>                     edit a `.level` file and its `.json` file, creating them if missing
+  list                             list worlds, burrows and levels
+  serve                            answer JSON requests on stdin, one per line: load, move,
+                                   use_item, undo, state or solve
+
+Options:
+  --burrows <dir>     directory containing the worlds [default: ./burrows]
+  --search <mode>     how to solve: bfs, parallel bfs on every core,
+                      or A* guided by line or grid distances [default: grid]
+  --check-collisions  compare visited states in full instead of only by their hash
+  --load <file>       resume playing from a saved game, and save to it
+                      [default for saving: ./<world>.save.json]
+
+Exit codes: 0 on success, 1 if a level is unsolvable or invalid,
+2 on wrong usage and 3 on any other error.";
+
+const EXIT_NEGATIVE: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_ERROR: u8 = 3;
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Play {
+        world: String,
+        level: Option<(String, usize)>,
+    },
+    Solve {
+        world: String,
+        burrow: String,
+        depth: usize,
+    },
+    Replay {
+        world: String,
+        burrow: String,
+        depth: usize,
+    },
+    Plan {
+        world: String,
+    },
+    Validate {
+        dir: PathBuf,
+    },
+    Render {
+        level: PathBuf,
+    },
+    Analyze {
+        level: PathBuf,
+    },
+    Edit {
+        level: PathBuf,
+    },
+    List,
+    Serve,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Cli {
+    burrows: PathBuf,
+    search: Search,
+    check_collisions: bool,
+    load: Option<PathBuf>,
+    command: Command,
+}
+
+impl Cli {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Cli, String> {
+        let mut burrows = PathBuf::from("burrows");
+        let mut search = Search::default();
+        let mut check_collisions = false;
+        let mut load = None;
+        let mut positional = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--burrows" => {
+                    burrows = args
+                        .next()
+                        .ok_or("Missing directory after --burrows")?
+                        .into()
+                }
+                "--search" => search = args.next().ok_or("Missing mode after --search")?.parse()?,
+                "--check-collisions" => check_collisions = true,
+                "--load" => load = Some(args.next().ok_or("Missing file after --load")?.into()),
+                "-h" | "--help" => return Err(String::new()),
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {arg}")),
+                _ => positional.push(arg),
+            }
+        }
+
+        let Some((name, rest)) = positional.split_first() else {
+            return Err("Missing command".to_owned());
+        };
+        let depth = |s: &String| s.parse::<usize>().map_err(|_| format!("Invalid depth {s}"));
+        let command = match (name.as_str(), rest) {
+            ("play", [world]) => Command::Play {
+                world: world.clone(),
+                level: None,
+            },
+            ("play", [world, burrow, d]) => Command::Play {
+                world: world.clone(),
+                level: Some((burrow.clone(), depth(d)?)),
+            },
+            ("solve", [world, burrow, d]) => Command::Solve {
+                world: world.clone(),
+                burrow: burrow.clone(),
+                depth: depth(d)?,
+            },
+            ("replay", [world, burrow, d]) => Command::Replay {
+                world: world.clone(),
+                burrow: burrow.clone(),
+                depth: depth(d)?,
+            },
+            ("plan", [world]) => Command::Plan {
+                world: world.clone(),
+            },
+            ("validate", [dir]) => Command::Validate { dir: dir.into() },
+            ("render", [level]) => Command::Render {
+                level: level.into(),
+            },
+            ("analyze", [level]) => Command::Analyze {
+                level: level.into(),
+            },
+            ("edit", [level]) => Command::Edit {
+                level: level.into(),
+            },
+            ("list", []) => Command::List,
+            ("serve", []) => Command::Serve,
+            (
+                "play" | "solve" | "replay" | "plan" | "validate" | "render" | "analyze" | "edit"
+                | "list" | "serve",
+                _,
+            ) => return Err(format!("Wrong number of arguments for {name}")),
+            _ => return Err(format!("Unknown command {name}")),
+        };
+        match command {
+            Command::Play { level: None, .. } => (),
+            Command::Play { .. } if load.is_some() => {
+                return Err("Can't start at a level with --load".to_owned())
+            }
+            _ if load.is_some() => return Err("--load only works with play".to_owned()),
+            _ => (),
+        }
+        Ok(Cli {
+            burrows,
+            search,
+            check_collisions,
+            load,
+            command,
+        })
+    }
+
+    /// Run the command, `Ok(false)` signals a negative result like an unsolvable level
+    fn execute(self) -> anyhow::Result<bool> {
+        match self.command {
+            Command::Play { world, level } => {
+                let worlds = bnys_loader::load_worlds(&self.burrows)?;
+                let world = find_world(&worlds, &world)?;
+                let mut state = match (&self.load, level) {
+                    (Some(path), _) => world.load_state(path)?,
+                    (None, None) => world.enter()?,
+                    (None, Some((burrow, depth))) => world.enter_at(&burrow, depth)?,
+                };
+                let save_path = self
+                    .load
+                    .unwrap_or_else(|| format!("{}.save.json", world.title()).into());
+                tui::run_world(&mut state, &save_path)?;
+                Ok(true)
+            }
+            Command::Solve {
+                world,
+                burrow,
+                depth,
+            } => {
+                let worlds = bnys_loader::load_worlds(&self.burrows)?;
+                let Some(state) = world_level(&worlds, &world, &burrow, depth)? else {
+                    return Ok(false);
+                };
+                let Some(solution) = solver::solve(&state, self.search, self.check_collisions)?
+                else {
+                    println!("Unsolvable");
+                    return Ok(false);
+                };
+                for action in &solution {
+                    println!("{action:?}");
+                }
+                println!("Solved in {} actions", solution.len());
+                Ok(true)
+            }
+            Command::Replay {
+                world,
+                burrow,
+                depth,
+            } => {
+                let worlds = bnys_loader::load_worlds(&self.burrows)?;
+                let Some(state) = world_level(&worlds, &world, &burrow, depth)? else {
+                    return Ok(false);
+                };
+                let Some(solution) = solver::solve(&state, self.search, self.check_collisions)?
+                else {
+                    println!("Unsolvable");
+                    return Ok(false);
+                };
+                tui::run_replay(&state, &solution)?;
+                Ok(true)
+            }
+            Command::Plan { world } => {
+                let worlds = bnys_loader::load_worlds(&self.burrows)?;
+                let world = find_world(&worlds, &world)?;
+                let plan = planner::plan_world(world, planner::DEFAULT_MAX_STATES)?;
+                let name = |id: LevelId| format!("{} {}", world.burrow_name(id.burrow), id.depth);
+                for step in &plan.steps {
+                    match step {
+                        Step::Level { level, actions } => {
+                            println!("{}:", name(*level));
+                            for action in actions {
+                                println!("  {action:?}");
+                            }
+                        }
+                        Step::Transition { from, to, d } => {
+                            println!("{} -> {} ({d:?})", name(*from), name(*to))
+                        }
+                    }
+                }
+                println!("Caught {} of {} buns", plan.caught, plan.total);
+                match plan.uncatchable {
+                    Some(0) => (),
+                    Some(n) => println!("{n} buns can't be caught"),
+                    None => println!("Gave up searching for the others"),
+                }
+                for level in plan.unreachable {
+                    println!("Unreachable: {}", name(level));
+                }
+                Ok(plan.caught == plan.total)
+            }
+            Command::Validate { dir } => {
+                let worlds = bnys_loader::load_worlds(&dir)?;
+                let mut valid = true;
+                for world in &worlds {
+                    for e in world.level_errors() {
+                        println!("{}: {e}", world.title());
+                        valid = false;
+                    }
+                }
+                Ok(valid)
+            }
+            Command::Render { level } => {
+                let Some(state) = read_level(&level)? else {
+                    return Ok(false);
+                };
+                println!("{}", state.to_unicode_string());
+                Ok(true)
+            }
+            Command::Analyze { level } => {
+                let Some(state) = read_level(&level)? else {
+                    return Ok(false);
+                };
+                tui::print_analysis(&state, &analysis::analyze(&state))?;
+                Ok(true)
+            }
+            Command::Edit { level } => {
+                let json = level.with_extension("json");
+                let mut state = if level.exists() {
+                    let Some(state) = read_level(&level)? else {
+                        return Ok(false);
+                    };
+                    state
+                } else {
+                    levels::walled()
+                };
+                let mut info = if json.exists() {
+                    bnys_loader::read_level_info(&json)?
+                } else {
+                    LevelInfo {
+                        name: level
+                            .file_stem()
+                            .map_or(String::new(), |s| s.to_string_lossy().into_owned()),
+                        ..Default::default()
+                    }
+                };
+                state.set_inventory(info.tools);
+                tui::run_editor(&mut state, &info.name.clone(), |state| {
+                    let data = state
+                        .to_level_string()
+                        .ok_or_else(|| anyhow!("The level can't be written as a `.level` file"))?;
+                    std::fs::write(&level, data)
+                        .with_context(|| format!("Writing {}", level.display()))?;
+                    info.tools = state.inventory();
+                    bnys_loader::write_level_info(&json, &info)
+                })?;
+                Ok(true)
+            }
+            Command::Serve => {
+                let stdin = std::io::stdin().lock();
+                serve::serve(
+                    stdin,
+                    std::io::stdout(),
+                    self.burrows,
+                    self.search,
+                    self.check_collisions,
+                )?;
+                Ok(true)
+            }
+            Command::List => {
+                let worlds = bnys_loader::load_worlds(&self.burrows)?;
+                for world in &worlds {
+                    println!("{}", world.title());
+                    for burrow in world.burrow_names() {
+                        println!("  {burrow}");
+                        for (depth, name) in world.level_names(&burrow) {
+                            println!("    {depth:>2} {name}");
+                        }
+                    }
+                }
+                Ok(true)
+            }
+        }
+    }
+}
+
+/// Read a `.level` file, printing the parse error and returning `None` if it is invalid
+fn read_level(path: &Path) -> anyhow::Result<Option<LevelState>> {
+    let data =
+        std::fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+    let mut state = LevelState::new();
+    if let Err(e) = state.parse_level(&data) {
+        println!("{}: {e}", path.display());
+        return Ok(None);
+    }
+    Ok(Some(state))
+}
+
+/// Load a level of a world, printing the parse error and returning `None` if it is invalid
+fn world_level(
+    worlds: &[World],
+    world: &str,
+    burrow: &str,
+    depth: usize,
+) -> anyhow::Result<Option<LevelState>> {
+    match find_world(worlds, world)?.load_level(burrow, depth) {
+        Ok(state) => Ok(Some(state)),
+        Err(e) => {
+            let e = e.downcast::<LevelError>()?;
+            println!("{world}: {e}");
+            Ok(None)
+        }
+    }
+}
+
+fn find_world<'a>(worlds: &'a [World], title: &str) -> anyhow::Result<&'a World> {
+    worlds
+        .iter()
+        .find(|w| w.title() == title)
+        .ok_or_else(|| anyhow!("Unknown world {title}"))
+}
+
+pub fn run(args: impl IntoIterator<Item = String>) -> ExitCode {
+    let cli = match Cli::parse(args) {
+        Ok(cli) => cli,
+        Err(msg) => {
+            if !msg.is_empty() {
+                eprintln!("{msg}\n");
+            }
+            eprintln!("{USAGE}");
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+    match cli.execute() {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(EXIT_NEGATIVE),
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{Cli, Command, Search};
+
+    fn parse(args: &[&str]) -> Result<Cli, String> {
+        Cli::parse(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse(&["--burrows", "dir", "solve", "World", "Burrow", "3"]),
+            Ok(Cli {
+                burrows: PathBuf::from("dir"),
+                search: Search::GridDistance,
+                check_collisions: false,
+                load: None,
+                command: Command::Solve {
+                    world: "World".to_owned(),
+                    burrow: "Burrow".to_owned(),
+                    depth: 3
+                }
+            })
+        );
+        assert!(parse(&["play"]).is_err());
+        assert!(parse(&["solve", "World", "Burrow", "deep"]).is_err());
+        assert_eq!(
+            parse(&["--load", "game.json", "play", "World"]).map(|cli| cli.load),
+            Ok(Some(PathBuf::from("game.json")))
+        );
+        assert!(parse(&["--load", "game.json", "play", "World", "Burrow", "1"]).is_err());
+        assert!(parse(&["--load", "game.json", "list"]).is_err());
+    }
+}
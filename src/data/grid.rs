@@ -1,15 +1,16 @@
+use serde::{Deserialize, Serialize};
 use std::ops::{Index, IndexMut, Neg};
 use strum::EnumCount;
 use strum_macros::{EnumIter, FromRepr, EnumCount};
 
-use super::Position;
+use super::{Item, Position};
 
 pub const LEVEL_WIDTH: i8 = 15;
 pub const LEVEL_HEIGHT: i8 = 9;
 
 pub(crate) type Tunnels = [bool;Direction::COUNT];
 
-#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
 pub enum GroundTile {
     Hole,
     Wall { breakable: bool, tunnels: Tunnels },
@@ -28,9 +29,12 @@ pub enum TileItem {
     Bun,
     #[allow(dead_code)]
     Bunstack,
+    Tool(Item),
+    Placed(Item),
 }
 
 #[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, FromRepr, EnumIter, EnumCount)]
+#[derive(Serialize, Deserialize)]
 pub enum Direction {
     Up = 0,
     Left = 1,
@@ -75,6 +79,7 @@ impl GroundTile {
     pub fn to_unicode(self) -> char {
         match self {
             GroundTile::Hole => 'o',
+            GroundTile::Wall { tunnels, .. } if tunnels.contains(&true) => tunnel_glyph(tunnels),
             GroundTile::Wall { breakable: b, .. } => {
                 if b {
                     '░'
@@ -92,12 +97,45 @@ impl GroundTile {
             GroundTile::Floor { .. } | GroundTile::Hole => false,
         }
     }
-    pub fn is_solid_for_bun_from(self, _: Direction) -> bool {
+    /// Whether a bun moving in direction `d` is blocked from entering this tile.
+    /// Tunnels can be entered through the opening facing the bun.
+    pub fn is_solid_for_bun_from(self, d: Direction) -> bool {
         match self {
-            GroundTile::Wall { .. } => true,
+            GroundTile::Wall { tunnels, .. } => !tunnels[-d],
             GroundTile::Floor { .. } | GroundTile::Hole => false,
         }
     }
+
+    pub fn is_tunnel(self) -> bool {
+        matches!(self, GroundTile::Wall { tunnels, .. } if tunnels.contains(&true))
+    }
+}
+
+/// Box drawing character connecting the open sides of a tunnel
+pub fn tunnel_glyph(tunnels: Tunnels) -> char {
+    match (
+        tunnels[Direction::Up],
+        tunnels[Direction::Left],
+        tunnels[Direction::Down],
+        tunnels[Direction::Right],
+    ) {
+        (false, false, false, false) => ' ',
+        (true, false, false, false) => '╵',
+        (false, true, false, false) => '╴',
+        (false, false, true, false) => '╷',
+        (false, false, false, true) => '╶',
+        (true, false, true, false) => '│',
+        (false, true, false, true) => '─',
+        (true, true, false, false) => '┘',
+        (true, false, false, true) => '└',
+        (false, true, true, false) => '┐',
+        (false, false, true, true) => '┌',
+        (true, true, true, false) => '┤',
+        (true, false, true, true) => '├',
+        (true, true, false, true) => '┴',
+        (false, true, true, true) => '┬',
+        (true, true, true, true) => '┼',
+    }
 }
 
 impl TileItem {
@@ -107,6 +145,8 @@ impl TileItem {
             TileItem::Paquerette => 'P',
             TileItem::Bun => 'b',
             TileItem::Bunstack => '🗼',
+            TileItem::Tool(item) => item.to_unicode(),
+            TileItem::Placed(item) => item.to_unicode().to_ascii_uppercase(),
         }
     }
 }
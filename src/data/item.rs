@@ -1,9 +1,25 @@
-use std::ops::{Index, IndexMut};
-use strum::EnumCount;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    collections::BTreeMap,
+    ops::{Index, IndexMut},
+};
+use strum::{EnumCount, IntoEnumIterator};
 use strum_macros::{EnumCount, EnumIter, FromRepr};
 
 #[derive(
-    Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, FromRepr, EnumIter, EnumCount,
+    Debug,
+    Hash,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Clone,
+    Copy,
+    FromRepr,
+    EnumIter,
+    EnumCount,
+    Serialize,
+    Deserialize,
 )]
 pub enum Item {
     Trap,
@@ -12,6 +28,17 @@ pub enum Item {
     Shovel,
 }
 
+impl Item {
+    pub fn to_unicode(self) -> char {
+        match self {
+            Item::Trap => 't',
+            Item::Pickaxe => 'p',
+            Item::Carrot => 'c',
+            Item::Shovel => 's',
+        }
+    }
+}
+
 impl<T> Index<Item> for [T; Item::COUNT] {
     type Output = T;
 
@@ -25,3 +52,28 @@ impl<T> IndexMut<Item> for [T; Item::COUNT] {
         &mut self[index as usize]
     }
 }
+
+/// Serde for inventories as maps from item names to counts, e.g. `{"Trap": 1, "Pickaxe": 0, ...}`,
+/// for use with `#[serde(with = "serde_inventory")]`. Missing items count 0.
+pub mod serde_inventory {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        inventory: &[u8; Item::COUNT],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let map: BTreeMap<Item, u8> = Item::iter().map(|item| (item, inventory[item])).collect();
+        map.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<[u8; Item::COUNT], D::Error> {
+        let map = BTreeMap::<Item, u8>::deserialize(deserializer)?;
+        let mut res = [0; Item::COUNT];
+        for (item, count) in map {
+            res[item] = count;
+        }
+        Ok(res)
+    }
+}
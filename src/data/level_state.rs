@@ -1,15 +1,330 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashSet},
+    fmt,
+    hash::{Hash, Hasher},
+};
+
+use serde::{Deserialize, Serialize};
+
 use super::{
-    grid::{Direction, GroundTile, TileItem, Tunnels, LEVEL_HEIGHT, LEVEL_WIDTH},
-    Position,
+    grid::{Direction, GroundTile, TileItem, LEVEL_HEIGHT, LEVEL_WIDTH},
+    item::serde_inventory,
+    zobrist, Item, Position,
 };
 
+use strum::{EnumCount, IntoEnumIterator};
+
 pub type TileContent = (GroundTile, Option<TileItem>);
 
-#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Default, Clone)]
+/// A way out of a level
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+pub enum LevelExit {
+    /// Through the hole at the position, into the level below
+    Down(Position),
+    /// Over the edge of the level, stepping from the position in the direction
+    Side(Position, Direction),
+    /// Through the entry at the position, into the level above
+    Up(Position),
+}
+
+impl LevelExit {
+    /// Where the exit arrives in the level it leads to
+    pub fn arrival(self) -> Position {
+        match self {
+            LevelExit::Down(p) | LevelExit::Up(p) => p,
+            LevelExit::Side(p, d) => p.across_edge(d),
+        }
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+pub enum BunState {
+    In(Position),
+    Caught,
+    /// Left the level, to be continued in the level the exit leads to
+    Escaped(LevelExit),
+}
+
+/// Attribute characters for tunnel openings of a wall token, e.g. `Wlr`
+pub const TUNNEL_ATTRIBUTES: [(char, Direction); 4] = [
+    ('u', Direction::Up),
+    ('l', Direction::Left),
+    ('d', Direction::Down),
+    ('r', Direction::Right),
+];
+
+/// Attribute characters for an item lying on a floor token, e.g. `Tp`
+pub const ITEM_ATTRIBUTES: [(char, Item); 4] = [
+    ('t', Item::Trap),
+    ('p', Item::Pickaxe),
+    ('c', Item::Carrot),
+    ('s', Item::Shovel),
+];
+
+fn attribute_value<T: Copy>(table: &[(char, T)], c: char) -> Option<T> {
+    table.iter().find(|(a, _)| *a == c).map(|(_, v)| *v)
+}
+
+fn attribute_char<T: PartialEq>(table: &[(char, T)], v: &T) -> char {
+    table.iter().find(|(_, w)| w == v).expect("in table").0
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ParseErrorKind {
+    MissingToken,
+    UnknownTile,
+    UnknownAttribute(char),
+    DuplicateAttribute(char),
+}
+
+/// Error while parsing a `.level` file, located at the offending token
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ParseError {
+    pub pos: Position,
+    pub token: String,
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // rows and columns are counted from 1, as in a text editor
+        let (row, col) = (self.pos.y() + 1, self.pos.x() + 1);
+        match self.kind {
+            ParseErrorKind::MissingToken => write!(f, "row {row}, column {col}: missing tile"),
+            ParseErrorKind::UnknownTile => {
+                write!(f, "row {row}, column {col}: unknown tile `{}`", self.token)
+            }
+            ParseErrorKind::UnknownAttribute(a) => write!(
+                f,
+                "row {row}, column {col}: unknown attribute `{a}` in `{}`",
+                self.token
+            ),
+            ParseErrorKind::DuplicateAttribute(a) => write!(
+                f,
+                "row {row}, column {col}: duplicate attribute `{a}` in `{}`",
+                self.token
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Why an [`Action`] can't be taken
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum MoveError {
+    /// the tile at the position is solid
+    Blocked(Position),
+    /// the target would be outside of the level
+    OutOfBounds,
+    /// the move leaves the level, which only a world can follow
+    Exit(LevelExit),
+    /// the exit leads to no level of the world
+    NoLevel(LevelExit),
+    /// the level behind an exit doesn't parse
+    InvalidLevel(ParseError),
+    /// the inventory holds none of the item
+    NoItem(Item),
+    /// the item can't be used on the tile at the position
+    Unusable(Item, Position),
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // rows and columns are counted from 1, as for parse errors
+        let at = |p: &Position| format!("row {}, column {}", p.y() + 1, p.x() + 1);
+        match self {
+            MoveError::Blocked(p) => write!(f, "the tile at {} is blocked", at(p)),
+            MoveError::OutOfBounds => write!(f, "target is outside of the level"),
+            MoveError::Exit(_) => write!(f, "the move leaves the level"),
+            MoveError::NoLevel(_) => write!(f, "there is no level behind the exit"),
+            MoveError::InvalidLevel(e) => write!(f, "the level behind the exit is invalid: {e}"),
+            MoveError::NoItem(item) => write!(f, "no {item:?} in the inventory"),
+            MoveError::Unusable(item, p) => {
+                write!(f, "{item:?} can't be used on the tile at {}", at(p))
+            }
+        }
+    }
+}
+
+impl std::error::Error for MoveError {}
+
+/// What Paquerette can do in one turn
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+pub enum Action {
+    Move(Direction),
+    /// Use an item from the inventory on the neighbouring tile in the direction
+    Use(Item, Direction),
+}
+
+/// The state of one level.
+///
+/// Buns are interchangeable: states only differing in the order of their buns are equal.
+/// The order is still kept, so the frames of a move can follow each bun.
+/// In JSON the tiles are given in rows from the top, as in `.level` files.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(into = "LevelStateJson", try_from = "LevelStateJson")]
 pub struct LevelState {
     data: Box<[[GroundTile; LEVEL_HEIGHT as usize]; LEVEL_WIDTH as usize]>,
     paquerette: Position,
-    buns: Vec<Option<Position>>,
+    buns: Vec<BunState>,
+    /// items lying around, to be picked up by Paquerette
+    items: BTreeMap<Position, Item>,
+    /// traps and carrots put down by Paquerette
+    placed: BTreeMap<Position, Item>,
+    inventory: [u8; Item::COUNT],
+    /// Zobrist key of all of the above, kept up to date by every change
+    zobrist: u64,
+}
+
+/// The JSON form of a [`LevelState`]
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LevelStateJson {
+    /// rows of tiles from the top
+    tiles: Vec<Vec<GroundTile>>,
+    paquerette: Position,
+    buns: Vec<BunState>,
+    #[serde(default)]
+    items: Vec<(Position, Item)>,
+    #[serde(default)]
+    placed: Vec<(Position, Item)>,
+    #[serde(default, with = "serde_inventory")]
+    inventory: [u8; Item::COUNT],
+}
+
+impl From<LevelState> for LevelStateJson {
+    fn from(state: LevelState) -> Self {
+        let tiles = (0..LEVEL_HEIGHT)
+            .map(|y| {
+                (0..LEVEL_WIDTH)
+                    .map(|x| state.tile_at((x, y).try_into().unwrap()).unwrap())
+                    .collect()
+            })
+            .collect();
+        LevelStateJson {
+            tiles,
+            paquerette: state.paquerette,
+            buns: state.buns,
+            items: state.items.into_iter().collect(),
+            placed: state.placed.into_iter().collect(),
+            inventory: state.inventory,
+        }
+    }
+}
+
+impl TryFrom<LevelStateJson> for LevelState {
+    type Error = String;
+
+    fn try_from(json: LevelStateJson) -> Result<Self, Self::Error> {
+        if json.tiles.len() != LEVEL_HEIGHT as usize
+            || json
+                .tiles
+                .iter()
+                .any(|row| row.len() != LEVEL_WIDTH as usize)
+        {
+            return Err(format!(
+                "tiles must be {LEVEL_HEIGHT} rows of {LEVEL_WIDTH}"
+            ));
+        }
+        let check = |p: Position| {
+            if p.is_inner() {
+                Ok(p)
+            } else {
+                Err(format!("position {p:?} is outside of the level"))
+            }
+        };
+        let mut res = LevelState::new();
+        for p in Position::iter() {
+            res.set_tile_at(p, json.tiles[p.y() as usize][p.x() as usize]);
+        }
+        res.set_paquerette(check(json.paquerette)?);
+        for bun in &json.buns {
+            match *bun {
+                BunState::In(p)
+                | BunState::Escaped(
+                    LevelExit::Down(p) | LevelExit::Up(p) | LevelExit::Side(p, _),
+                ) => {
+                    check(p)?;
+                }
+                BunState::Caught => (),
+            }
+        }
+        res.set_buns(json.buns);
+        for (p, item) in json.items {
+            res.insert_item(check(p)?, item);
+        }
+        for (p, item) in json.placed {
+            res.set_placed_at(check(p)?, item);
+        }
+        res.set_inventory(json.inventory);
+        Ok(res)
+    }
+}
+
+/// Changes to a [`LevelState`] leaving its Zobrist key alone, see [`LevelState::unkeyed`]
+pub(crate) struct Unkeyed<'a>(&'a mut LevelState);
+
+impl Unkeyed<'_> {
+    pub fn set_tile_at(&mut self, p: Position, t: GroundTile) {
+        let (x, y) = self.0.index_for(p).expect("in range");
+        self.0.data[x][y] = t;
+    }
+
+    pub fn set_paquerette(&mut self, p: Position) {
+        self.0.paquerette = p;
+    }
+
+    pub fn set_buns(&mut self, buns: impl IntoIterator<Item = BunState>) {
+        self.0.buns.clear();
+        self.0.buns.extend(buns);
+    }
+
+    pub fn remove_item_at(&mut self, p: Position) {
+        self.0.items.remove(&p);
+    }
+
+    pub fn set_placed_at(&mut self, p: Position, item: Item) {
+        self.0.placed.insert(p, item);
+    }
+
+    pub fn set_inventory(&mut self, inventory: [u8; Item::COUNT]) {
+        self.0.inventory = inventory;
+    }
+}
+
+/// Equal states have equal keys, so hashing the key alone is enough
+impl Hash for LevelState {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.zobrist.hash(state)
+    }
+}
+
+impl PartialEq for LevelState {
+    fn eq(&self, other: &Self) -> bool {
+        self.zobrist == other.zobrist && self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for LevelState {}
+
+impl PartialOrd for LevelState {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for LevelState {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.data
+            .cmp(&other.data)
+            .then(Ord::cmp(&self.paquerette, &other.paquerette))
+            .then_with(|| self.sorted_buns().cmp(other.sorted_buns()))
+            .then_with(|| self.items.cmp(&other.items))
+            .then_with(|| self.placed.cmp(&other.placed))
+            .then(self.inventory.cmp(&other.inventory))
+    }
 }
 
 impl LevelState {
@@ -22,29 +337,215 @@ impl LevelState {
         let mut res_item = None;
         if self.paquerette == p {
             res_item = Some(TileItem::Paquerette);
-        } else if self.buns.contains(&Some(p)) {
+        } else if self.buns.contains(&BunState::In(p)) {
             res_item = Some(TileItem::Bun);
+        } else if let Some(&item) = self.items.get(&p) {
+            res_item = Some(TileItem::Tool(item));
+        } else if let Some(&item) = self.placed.get(&p) {
+            res_item = Some(TileItem::Placed(item));
         }
         Some((self.data[x][y], res_item))
     }
 
+    /// The ground at `p`, without looking for what is on it
+    pub fn tile_at(&self, p: Position) -> Option<GroundTile> {
+        let (x, y) = self.index_for(p)?;
+        Some(self.data[x][y])
+    }
+
     pub fn set_tile_at(&mut self, p: Position, t: GroundTile) {
         let (x, y) = self.index_for(p).expect("in range");
+        self.rekey(zobrist::tile(p, self.data[x][y]), zobrist::tile(p, t));
         self.data[x][y] = t;
     }
 
     pub fn set_paquerette(&mut self, p: Position) {
+        self.rekey(zobrist::paquerette(self.paquerette), zobrist::paquerette(p));
         self.paquerette = p
     }
 
     pub fn set_bunny(&mut self, p: Position) {
-        self.buns.push(Some(p))
+        self.rekey(0, zobrist::bun(BunState::In(p)));
+        self.buns.push(BunState::In(p))
+    }
+
+    /// Take away a bun at `p`, returning whether there was one
+    pub fn remove_bunny(&mut self, p: Position) -> bool {
+        let Some(index) = self.buns.iter().position(|&b| b == BunState::In(p)) else {
+            return false;
+        };
+        self.rekey(zobrist::bun(BunState::In(p)), 0);
+        self.buns.remove(index);
+        true
+    }
+
+    /// Catch a bun at `p`, returning whether there was one
+    pub fn catch_bunny(&mut self, p: Position) -> bool {
+        let Some(index) = self.buns.iter().position(|&b| b == BunState::In(p)) else {
+            return false;
+        };
+        self.set_bun(index, BunState::Caught);
+        true
+    }
+
+    fn set_bun(&mut self, index: usize, bun: BunState) {
+        self.rekey(zobrist::bun(self.buns[index]), zobrist::bun(bun));
+        self.buns[index] = bun;
+    }
+
+    /// Put an item at `p` for Paquerette to pick up, returning the one lying there before
+    pub fn insert_item(&mut self, p: Position, item: Item) -> Option<Item> {
+        let old = self.items.insert(p, item);
+        self.rekey(
+            old.map_or(0, |old| zobrist::item(p, old)),
+            zobrist::item(p, item),
+        );
+        old
+    }
+
+    pub fn get_item_at(&self, p: Position) -> Option<Item> {
+        self.items.get(&p).copied()
+    }
+
+    /// The items lying around
+    pub fn items(&self) -> impl Iterator<Item = (Position, Item)> + '_ {
+        self.items.iter().map(|(&p, &item)| (p, item))
+    }
+
+    pub fn remove_item_at(&mut self, p: Position) -> Option<Item> {
+        let item = self.items.remove(&p)?;
+        self.rekey(zobrist::item(p, item), 0);
+        Some(item)
+    }
+
+    /// The traps and carrots Paquerette put down
+    pub fn placed(&self) -> impl Iterator<Item = (Position, Item)> + '_ {
+        self.placed.iter().map(|(&p, &item)| (p, item))
+    }
+
+    /// The trap or carrot Paquerette put down at `p`
+    pub fn get_placed_at(&self, p: Position) -> Option<Item> {
+        self.placed.get(&p).copied()
+    }
+
+    pub fn set_placed_at(&mut self, p: Position, item: Item) {
+        let old = self.placed.insert(p, item);
+        self.rekey(
+            old.map_or(0, |old| zobrist::placed(p, old)),
+            zobrist::placed(p, item),
+        );
+    }
+
+    fn remove_placed_at(&mut self, p: Position) -> Option<Item> {
+        let item = self.placed.remove(&p)?;
+        self.rekey(zobrist::placed(p, item), 0);
+        Some(item)
+    }
+
+    pub fn clear_placed(&mut self) {
+        for (p, item) in std::mem::take(&mut self.placed) {
+            self.rekey(zobrist::placed(p, item), 0);
+        }
     }
 
     pub fn get_paquerette(&self) -> Position {
         self.paquerette
     }
 
+    pub fn inventory(&self) -> [u8; Item::COUNT] {
+        self.inventory
+    }
+
+    pub fn set_inventory(&mut self, inventory: [u8; Item::COUNT]) {
+        for item in Item::iter() {
+            self.set_inventory_count(item, inventory[item]);
+        }
+    }
+
+    pub fn add_inventory(&mut self, items: [u8; Item::COUNT]) {
+        for item in Item::iter() {
+            self.set_inventory_count(item, self.inventory[item].saturating_add(items[item]));
+        }
+    }
+
+    fn set_inventory_count(&mut self, item: Item, count: u8) {
+        self.rekey(
+            zobrist::inventory(item, self.inventory[item]),
+            zobrist::inventory(item, count),
+        );
+        self.inventory[item] = count;
+    }
+
+    /// Whether every bun of the level has been caught
+    pub fn is_solved(&self) -> bool {
+        self.buns.iter().all(|b| *b == BunState::Caught)
+    }
+
+    pub fn buns(&self) -> &[BunState] {
+        &self.buns
+    }
+
+    /// The buns in ascending order, the same for all orders of the same buns.
+    /// Picks each next bun from the slice instead of sorting a copy, as states are compared a lot.
+    fn sorted_buns(&self) -> impl Iterator<Item = BunState> + '_ {
+        // the last bun returned and how many equal ones are still to come
+        let mut last: Option<(BunState, usize)> = None;
+        std::iter::from_fn(move || {
+            if let Some((bun, left)) = &mut last {
+                if *left > 0 {
+                    *left -= 1;
+                    return Some(*bun);
+                }
+            }
+            let bun = *self
+                .buns
+                .iter()
+                .filter(|&&b| last.is_none_or(|(l, _)| b > l))
+                .min()?;
+            let count = self.buns.iter().filter(|&&b| b == bun).count();
+            last = Some((bun, count - 1));
+            Some(bun)
+        })
+    }
+
+    pub fn set_buns(&mut self, buns: Vec<BunState>) {
+        for bun in std::mem::replace(&mut self.buns, buns) {
+            self.rekey(zobrist::bun(bun), 0);
+        }
+        for i in 0..self.buns.len() {
+            self.rekey(0, zobrist::bun(self.buns[i]));
+        }
+    }
+
+    /// Forget the escaped buns whose exit satisfies `f`
+    pub fn release_escaped(&mut self, mut f: impl FnMut(LevelExit) -> bool) {
+        let zobrist = &mut self.zobrist;
+        self.buns.retain(|&b| match b {
+            BunState::Escaped(exit) if f(exit) => {
+                *zobrist = zobrist.wrapping_sub(zobrist::bun(b));
+                false
+            }
+            _ => true,
+        })
+    }
+
+    /// The Zobrist key of the state, equal for equal states
+    pub fn zobrist(&self) -> u64 {
+        self.zobrist
+    }
+
+    /// Change the state without updating its Zobrist key change by change,
+    /// taking `key`, the key of the changed state, instead
+    pub(crate) fn unkeyed(&mut self, key: u64) -> Unkeyed<'_> {
+        self.zobrist = key;
+        Unkeyed(self)
+    }
+
+    /// Replace the key `old` of a part of the state with the key `new`
+    fn rekey(&mut self, old: u64, new: u64) {
+        self.zobrist = self.zobrist.wrapping_sub(old).wrapping_add(new);
+    }
+
     pub fn content(&self) -> impl Iterator<Item = (Position, TileContent)> + '_ {
         Position::iter().map(|p| (p, self.get_at(p).expect("should be inbound")))
     }
@@ -66,7 +567,12 @@ impl LevelState {
         res
     }
 
-    pub fn parse_level(&mut self, input: &str) -> Result<(), ()>
+    /// Parse the comma or whitespace separated tokens of a `.level` file.
+    ///
+    /// Each token starts with a tile character (`W`, `R`, `T`, `E`, `S` or `B`),
+    /// optionally followed by attributes: tunnel openings on walls
+    /// (see [`TUNNEL_ATTRIBUTES`]) or an item lying on a floor tile (see [`ITEM_ATTRIBUTES`]).
+    pub fn parse_level(&mut self, input: &str) -> Result<(), ParseError>
     where
         Self: Sized,
     {
@@ -74,66 +580,219 @@ impl LevelState {
             .split(|c: char| c.is_whitespace() || c == ',')
             .filter(|str| !str.is_empty());
         for p in Position::iter() {
-            let c = segs.next().ok_or(())?;
-            let (c, rem) = c.split_at(1);
-            if !rem.is_empty() {
-                eprintln!("Extra Tile attributes not yet implemented");
-                return Err(());
-            }
-            let mut tile: GroundTile = GroundTile::Floor { is_entry: false };
-            let tunnels: Tunnels = Default::default();
-            if c == "W" {
-                tile = GroundTile::Wall {
-                    breakable: false,
-                    tunnels,
-                };
-            } else if c == "R" {
-                tile = GroundTile::Wall {
-                    breakable: true,
-                    tunnels,
-                };
-            } else if c == "T" {
-                tile = GroundTile::Floor { is_entry: false };
-            } else if c == "E" {
-                tile = GroundTile::Hole;
-            } else if c == "S" {
-                tile = GroundTile::Floor { is_entry: true };
+            let token = segs.next().ok_or(ParseError {
+                pos: p,
+                token: String::new(),
+                kind: ParseErrorKind::MissingToken,
+            })?;
+            self.parse_token(p, token).map_err(|kind| ParseError {
+                pos: p,
+                token: token.to_owned(),
+                kind,
+            })?;
+        }
+        Ok(())
+    }
+
+    fn parse_token(&mut self, p: Position, token: &str) -> Result<(), ParseErrorKind> {
+        let mut chars = token.chars();
+        let c = chars.next().expect("tokens are non-empty");
+        let mut tile = match c {
+            'W' | 'R' => GroundTile::Wall {
+                breakable: c == 'R',
+                tunnels: Default::default(),
+            },
+            'T' => GroundTile::Floor { is_entry: false },
+            'E' => GroundTile::Hole,
+            'S' => {
                 self.set_paquerette(p);
-            } else if c == "B" {
+                GroundTile::Floor { is_entry: true }
+            }
+            'B' => {
                 self.set_bunny(p);
-            } else {
-                eprintln!("Level object {c} not yet implemented");
-                return Err(());
+                GroundTile::Floor { is_entry: false }
+            }
+            _ => return Err(ParseErrorKind::UnknownTile),
+        };
+        for a in chars {
+            match &mut tile {
+                GroundTile::Wall { tunnels, .. } => {
+                    let d = attribute_value(&TUNNEL_ATTRIBUTES, a)
+                        .ok_or(ParseErrorKind::UnknownAttribute(a))?;
+                    if tunnels[d] {
+                        return Err(ParseErrorKind::DuplicateAttribute(a));
+                    }
+                    tunnels[d] = true;
+                }
+                GroundTile::Floor { .. } => {
+                    let item = attribute_value(&ITEM_ATTRIBUTES, a)
+                        .ok_or(ParseErrorKind::UnknownAttribute(a))?;
+                    if self.insert_item(p, item).is_some() {
+                        return Err(ParseErrorKind::DuplicateAttribute(a));
+                    }
+                }
+                GroundTile::Hole => return Err(ParseErrorKind::UnknownAttribute(a)),
             }
-            self.set_tile_at(p, tile);
         }
+        self.set_tile_at(p, tile);
         Ok(())
     }
 
+    /// Write the state as the tokens of a `.level` file, see [`Self::parse_level`].
+    ///
+    /// Only states as they are before playing can be written, `None` for others:
+    /// `.level` files have no tokens for Paquerette away from the entry,
+    /// caught or escaped buns, buns in tunnels or the items she put down.
+    pub fn to_level_string(&self) -> Option<String> {
+        let mut res = String::new();
+        for p in Position::iter() {
+            if p.x() > 0 {
+                res.push(',');
+            } else if p.y() > 0 {
+                res.push('\n');
+            }
+            res += &self.level_token(p);
+        }
+        let mut parsed = LevelState::new();
+        parsed.set_inventory(self.inventory);
+        parsed.parse_level(&res).ok()?;
+        (parsed == *self).then_some(res)
+    }
+
+    /// The `.level` token of the tile at `p`, see [`Self::to_level_string`]
+    pub fn level_token(&self, p: Position) -> String {
+        let mut res = String::new();
+        match self.tile_at(p).expect("in range") {
+            GroundTile::Hole => res.push('E'),
+            GroundTile::Wall { breakable, tunnels } => {
+                res.push(if breakable { 'R' } else { 'W' });
+                for (a, d) in TUNNEL_ATTRIBUTES {
+                    if tunnels[d] {
+                        res.push(a);
+                    }
+                }
+            }
+            GroundTile::Floor { .. } => {
+                res.push(if p == self.paquerette {
+                    'S'
+                } else if self.buns.contains(&BunState::In(p)) {
+                    'B'
+                } else {
+                    'T'
+                });
+                if let Some(item) = self.get_item_at(p) {
+                    res.push(attribute_char(&ITEM_ATTRIBUTES, &item));
+                }
+            }
+        }
+        res
+    }
+
     pub fn index_for(&self, p: Position) -> Option<(usize, usize)> {
         p.into_clamped_usize(LEVEL_WIDTH, LEVEL_HEIGHT)
     }
 
-    pub fn move_to(&mut self, d: Direction) -> Result<Vec<Self>, ()> {
+    /// The exit Paquerette takes when moving in direction `d`, if it leaves the level
+    pub fn exit_for(&self, d: Direction) -> Option<LevelExit> {
         let new_pos = self.get_paquerette() + d;
-        if self.get_at(new_pos).ok_or(())?.0.is_solid() {
-            return Err(());
+        match self.get_at(new_pos) {
+            None => Some(LevelExit::Side(self.get_paquerette(), d)),
+            Some((GroundTile::Hole, _)) => Some(LevelExit::Down(new_pos)),
+            Some((GroundTile::Floor { is_entry: true }, _)) => Some(LevelExit::Up(new_pos)),
+            Some(_) => None,
         }
-        self.set_paquerette(new_pos);
+    }
 
+    /// The actions that may be possible in this state
+    pub fn actions(&self) -> impl Iterator<Item = Action> + '_ {
+        let uses = Item::iter()
+            .filter(|&item| self.inventory[item] > 0)
+            .flat_map(|item| Direction::iter().map(move |d| Action::Use(item, d)));
+        Direction::iter().map(Action::Move).chain(uses)
+    }
+
+    pub fn apply(&mut self, action: Action) -> Result<Vec<Self>, MoveError> {
+        match action {
+            Action::Move(d) => self.move_to(d),
+            Action::Use(item, d) => self.use_item(item, d).map(|()| Vec::new()),
+        }
+    }
+
+    /// Use an item from the inventory on the neighbouring tile in direction `d`:
+    /// put down a trap or a carrot, break a breakable wall with a pickaxe or dig a hole with a shovel
+    pub fn use_item(&mut self, item: Item, d: Direction) -> Result<(), MoveError> {
+        if self.inventory[item] == 0 {
+            return Err(MoveError::NoItem(item));
+        }
+        let target = self.get_paquerette() + d;
+        let (tile, tile_item) = self.get_at(target).ok_or(MoveError::OutOfBounds)?;
+        let is_free = tile == GroundTile::Floor { is_entry: false } && tile_item.is_none();
+        let is_breakable = matches!(
+            tile,
+            GroundTile::Wall {
+                breakable: true,
+                ..
+            }
+        );
+        match item {
+            Item::Trap | Item::Carrot if is_free => {
+                self.set_placed_at(target, item);
+            }
+            Item::Pickaxe if is_breakable => {
+                self.set_tile_at(target, GroundTile::Floor { is_entry: false });
+            }
+            Item::Shovel if is_free => self.set_tile_at(target, GroundTile::Hole),
+            _ => return Err(MoveError::Unusable(item, target)),
+        }
+        self.set_inventory_count(item, self.inventory[item] - 1);
+        Ok(())
+    }
+
+    /// Like [`Self::apply`], without building the frames of moves, for searches
+    pub fn apply_without_frames(&mut self, action: Action) -> Result<(), MoveError> {
+        match action {
+            Action::Move(d) => self.move_with(d, |_, _, _| ()),
+            Action::Use(item, d) => self.use_item(item, d),
+        }
+    }
+
+    /// Move Paquerette inside the level and let the buns react.
+    /// Moves leaving the level (see [`Self::exit_for`]) are rejected.
+    pub fn move_to(&mut self, d: Direction) -> Result<Vec<Self>, MoveError> {
         let mut res = Vec::new();
+        self.move_with(d, |state, index, bun| {
+            let mut frame = state.clone();
+            frame.set_bun(index, BunState::In(bun));
+            res.push(frame);
+        })?;
+        Ok(res)
+    }
 
-        for bun_index in 0..self.buns.len() {
-            let Some(mut bun) = self.buns[bun_index] else {
-                continue;
-            };
+    /// [`Self::move_to`], calling `frame` with the state, the index of a moving bun
+    /// and its position for every step the bun takes
+    fn move_with(
+        &mut self,
+        d: Direction,
+        mut frame: impl FnMut(&Self, usize, Position),
+    ) -> Result<(), MoveError> {
+        if let Some(exit) = self.exit_for(d) {
+            return Err(MoveError::Exit(exit));
+        }
+        let new_pos = self.get_paquerette() + d;
+        let (tile, _) = self.get_at(new_pos).ok_or(MoveError::OutOfBounds)?;
+        if tile.is_solid() {
+            return Err(MoveError::Blocked(new_pos));
+        }
+        self.set_paquerette(new_pos);
+        if let Some(item) = self.remove_item_at(new_pos) {
+            self.set_inventory_count(item, self.inventory[item].saturating_add(1));
+        }
 
+        for bun_index in 0..self.buns.len() {
             // if not in level anymore, skip
-            if !bun.is_inner() {
+            let BunState::In(mut bun) = self.buns[bun_index] else {
                 continue;
-            }
-
-            //TODO: loop this for tunnels
+            };
 
             //if can't see paquerette, skip
             let Some((dir, dist)) = self.paquerette.distance_to_straight_line(bun) else {
@@ -141,7 +800,7 @@ impl LevelState {
             };
 
             if dist == 0 {
-                self.buns[bun_index] = None;
+                self.set_bun(bun_index, BunState::Caught);
                 continue;
             }
 
@@ -160,55 +819,95 @@ impl LevelState {
             // let mut started_moving = false;
             // let mut last_branch_alternative: Option<(Position, Direction)> = None;
             // let mut last_branch_was_after_move = false;
-            loop {
-                let mut dir_to_move = None;
-                let dir_cands = [dir, dir.turn_left(), dir.turn_right()];
+            let mut dir_to_move = None;
+            let dir_cands = [dir, dir.turn_left(), dir.turn_right()];
+            for dir_try in dir_cands {
+                if self.bun_can_step(bun, dir_try) && !self.bun_can_see_deadend(bun, dir_try) {
+                    dir_to_move = Some(dir_try);
+                    break;
+                }
+            }
+
+            if dir_to_move.is_none() {
+                // otherwise, move to first free direction
                 for dir_try in dir_cands {
-                    if !self.bun_can_see_deadend(bun, dir_try) {
+                    if self.bun_can_step(bun, dir_try) {
                         dir_to_move = Some(dir_try);
                         break;
                     }
                 }
+            }
 
-                if dir_to_move.is_none() {
-                    // otherwise, move to first free direction
-                    for dir_try in dir_cands {
-                        if !self.is_solid_for_bun_from(bun + dir_try, dir_try) {
-                            dir_to_move = Some(dir_try);
-                            break;
-                        }
-                    }
-                }
-
-                let Some(dir_to_move) = dir_to_move else {
-                    break;
-                };
-
+            if let Some(mut dir_to_move) = dir_to_move {
                 // started_moving = true;
                 bun += dir_to_move;
-                while self
-                    .is_solid_for_bun_from(bun + dir_to_move.turn_left(), dir_to_move.turn_left())
-                    && self.is_solid_for_bun_from(
+                // the tunnel steps taken, to stop in a ring of tunnels
+                let mut tunnel_steps = HashSet::new();
+                loop {
+                    if !bun.is_inner() {
+                        self.set_bun(
+                            bun_index,
+                            BunState::Escaped(LevelExit::Side(bun + -dir_to_move, dir_to_move)),
+                        );
+                        break;
+                    }
+                    if self.get_at(bun).is_some_and(|(t, _)| t == GroundTile::Hole) {
+                        frame(self, bun_index, bun);
+                        self.set_bun(bun_index, BunState::Escaped(LevelExit::Down(bun)));
+                        break;
+                    }
+                    if let Some(placed) = self.remove_placed_at(bun) {
+                        // a trap catches the bun, a carrot makes it stop to eat
+                        frame(self, bun_index, bun);
+                        if placed == Item::Trap {
+                            self.set_bun(bun_index, BunState::Caught);
+                        }
+                        break;
+                    }
+                    if self.get_at(bun).is_some_and(|(t, _)| t.is_tunnel()) {
+                        // follow the tunnel, it can't be left through the entrance
+                        let exit = [
+                            dir_to_move,
+                            dir_to_move.turn_left(),
+                            dir_to_move.turn_right(),
+                        ]
+                        .into_iter()
+                        .find(|&d| self.bun_can_step(bun, d));
+                        let Some(exit) = exit else {
+                            break;
+                        };
+                        if !tunnel_steps.insert((bun, exit)) {
+                            break;
+                        }
+                        dir_to_move = exit;
+                    } else if !(self.is_solid_for_bun_from(
+                        bun + dir_to_move.turn_left(),
+                        dir_to_move.turn_left(),
+                    ) && self.is_solid_for_bun_from(
                         bun + dir_to_move.turn_right(),
                         dir_to_move.turn_right(),
-                    )
-                    && !self.is_solid_for_bun_from(bun + dir_to_move, dir_to_move)
-                {
-                    res.push(self.clone());
-                    res.last_mut().unwrap().buns[bun_index] = Some(bun);
+                    ) && !self.is_solid_for_bun_from(bun + dir_to_move, dir_to_move))
+                    {
+                        break;
+                    }
+                    frame(self, bun_index, bun);
                     bun += dir_to_move;
                 }
-
-                break;
             }
-            self.buns[bun_index] = Some(bun);
+            if let BunState::In(_) = self.buns[bun_index] {
+                self.set_bun(bun_index, BunState::In(bun));
+            }
         }
-        Ok(res)
+        Ok(())
     }
 
     pub fn bun_can_see_deadend(&self, mut cur_pos: Position, dir: Direction) -> bool {
         cur_pos += dir;
         while !self.is_solid_for_bun_from(cur_pos, dir) && cur_pos.is_inner() {
+            if self.get_at(cur_pos).is_some_and(|(t, _)| t.is_tunnel()) {
+                // buns know where their tunnels lead
+                return false;
+            }
             //println!("{dir:?}{cur_pos:?}");
             cur_pos += dir;
             let left = dir.turn_left();
@@ -223,12 +922,274 @@ impl LevelState {
         true
     }
 
+    /// Whether a bun at `p` can take a step in direction `d`.
+    /// Tunnels are only entered and left through their openings.
+    pub fn bun_can_step(&self, p: Position, d: Direction) -> bool {
+        let can_leave = match self.tile_at(p) {
+            Some(GroundTile::Wall { tunnels, .. }) => tunnels[d],
+            _ => true,
+        };
+        can_leave && !self.is_solid_for_bun_from(p + d, d)
+    }
+
     pub fn is_solid_for_bun_from(&self, p: Position, dir: Direction) -> bool {
-        self.get_at(p)
-            .is_some_and(|(t, _)| t.is_solid_for_bun_from(dir))
+        self.tile_at(p)
+            .is_some_and(|t| t.is_solid_for_bun_from(dir))
     }
 
     pub fn is_solid(&self, p: Position) -> bool {
-        self.get_at(p).is_some_and(|t| t.0.is_solid())
+        self.tile_at(p).is_some_and(|t| t.is_solid())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::{
+        grid::{Direction, GroundTile, TileItem},
+        levels::level,
+        Item, Position,
+    };
+
+    use super::{Action, BunState, LevelExit, LevelState, MoveError, ParseError, ParseErrorKind};
+
+    fn tokens(first: &str) -> String {
+        let mut res = vec!["T"; 15 * 9];
+        res[0] = first;
+        res.join(",")
+    }
+
+    #[test]
+    fn test_parse_attributes() {
+        let mut state = LevelState::new();
+        state.parse_level(&tokens("Rld")).unwrap();
+        let p = Position::default();
+        assert_eq!(
+            state.get_at(p).unwrap().0,
+            GroundTile::Wall {
+                breakable: true,
+                tunnels: [false, true, true, false]
+            }
+        );
+
+        let mut state = LevelState::new();
+        state.parse_level(&tokens("Tc")).unwrap();
+        assert_eq!(state.get_item_at(p), Some(Item::Carrot));
+    }
+
+    #[test]
+    fn test_level_string_round_trip() {
+        let data = tokens("Rld,Tc,Bp,Wu,S");
+        let mut state = LevelState::new();
+        state.parse_level(&data).unwrap();
+        let written = state.to_level_string().unwrap();
+        let mut parsed = LevelState::new();
+        parsed.parse_level(&written).unwrap();
+        assert_eq!(parsed, state);
+        assert!(written.starts_with("Rld,Tc,Bp,Wu,S,T"));
+
+        // once played, a level has no `.level` form
+        let mut played = state.clone();
+        played.move_to(Direction::Down).unwrap();
+        assert_eq!(played.to_level_string(), None);
+        let mut played = state.clone();
+        played.add_inventory([1, 0, 0, 0]);
+        played.use_item(Item::Trap, Direction::Down).unwrap();
+        assert_eq!(played.to_level_string(), None);
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let mut state = level(&["###############", "#P.b..=.......#", "#..%..........#"]);
+        state.add_inventory([1, 0, 2, 0]);
+        state.insert_item(Position::try_from((5, 2)).unwrap(), Item::Shovel);
+        state.move_to(Direction::Right).unwrap();
+        state.use_item(Item::Trap, Direction::Down).unwrap();
+        let json = serde_json::to_string(&state).unwrap();
+        assert!(json.contains(r#""inventory":{"Trap":0,"Pickaxe":0,"Carrot":2,"Shovel":0}"#));
+        let parsed: LevelState = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, state);
+        assert_eq!(parsed.zobrist(), state.zobrist());
+
+        let short = json.replacen("[[", "[", 1);
+        assert!(serde_json::from_str::<LevelState>(&short).is_err());
+
+        // positions of escaped buns are checked like the others
+        let escaped = |x| {
+            let exit = LevelExit::Side(Position::try_from((x, 1)).unwrap(), Direction::Right);
+            let mut json = serde_json::to_value(&state).unwrap();
+            json["buns"] = serde_json::to_value([BunState::Escaped(exit)]).unwrap();
+            serde_json::from_value::<LevelState>(json)
+        };
+        assert!(escaped(14).is_ok());
+        assert!(escaped(20).is_err());
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let mut state = LevelState::new();
+        assert_eq!(
+            state.parse_level(&tokens("Wx")),
+            Err(ParseError {
+                pos: Position::default(),
+                token: "Wx".to_owned(),
+                kind: ParseErrorKind::UnknownAttribute('x'),
+            })
+        );
+    }
+
+    #[test]
+    fn test_bun_through_tunnel() {
+        let mut state = level(&["###############", "#P.b=.."]);
+        let frames = state.move_to(Direction::Right).unwrap();
+        assert_eq!(frames.len(), 2);
+        let bun = Position::try_from((6, 1)).unwrap();
+        assert_eq!(state.get_at(bun).unwrap().1, Some(TileItem::Bun));
+
+        // Paquerette can't follow through the tunnel
+        state.move_to(Direction::Right).unwrap();
+        let tunnel = Position::try_from((4, 1)).unwrap();
+        assert_eq!(
+            state.move_to(Direction::Right),
+            Err(MoveError::Blocked(tunnel))
+        );
+    }
+
+    #[test]
+    fn test_bun_stops_in_tunnel_ring() {
+        let mut rows = [["W"; 15]; 9];
+        rows[1][4] = "S";
+        rows[2][4] = "T";
+        rows[3][4] = "B";
+        rows[4][4] = "Wurd";
+        rows[4][5] = "Wld";
+        rows[5][4] = "Wur";
+        rows[5][5] = "Wul";
+        let data: Vec<_> = rows.iter().map(|r| r.join(",")).collect();
+        let mut state = LevelState::new();
+        state.parse_level(&data.join("\n")).unwrap();
+
+        // the bun runs around the ring once and stops where it would repeat itself
+        let frames = state.move_to(Direction::Down).unwrap();
+        assert_eq!(frames.len(), 4);
+        let stop = Position::try_from((4, 4)).unwrap();
+        assert_eq!(state.buns(), [BunState::In(stop)]);
+    }
+
+    #[test]
+    fn test_bun_escapes_through_hole() {
+        let mut state = level(&["###############", "#P.bo"]);
+        state.move_to(Direction::Right).unwrap();
+        let hole = Position::try_from((4, 1)).unwrap();
+        assert_eq!(state.buns(), [BunState::Escaped(LevelExit::Down(hole))]);
+        assert_eq!(state.get_at(hole).unwrap().1, None);
+    }
+
+    #[test]
+    fn test_bun_caught_in_trap() {
+        let mut state = level(&["###############", "#P.b.."]);
+        state.set_placed_at(Position::try_from((4, 1)).unwrap(), Item::Trap);
+        state.move_to(Direction::Right).unwrap();
+        assert_eq!(state.buns(), [BunState::Caught]);
+        assert!(state.placed.is_empty());
+    }
+
+    #[test]
+    fn test_use_pickaxe() {
+        let mut state = level(&["###############", "#P%.b"]);
+        let pickaxe = Action::Use(Item::Pickaxe, Direction::Right);
+        assert_eq!(state.apply(pickaxe), Err(MoveError::NoItem(Item::Pickaxe)));
+        state.add_inventory([0, 1, 0, 0]);
+        // plain walls can't be broken
+        let wall = Position::try_from((1, 0)).unwrap();
+        assert_eq!(
+            state.use_item(Item::Pickaxe, Direction::Up),
+            Err(MoveError::Unusable(Item::Pickaxe, wall))
+        );
+        state.apply(pickaxe).unwrap();
+        assert_eq!(state.inventory(), [0; 4]);
+        state.apply(Action::Move(Direction::Right)).unwrap();
+    }
+
+    /// The same state built from scratch, in a different order
+    fn rebuilt(state: &LevelState) -> LevelState {
+        let mut res = LevelState::new();
+        res.add_inventory(state.inventory());
+        for (p, (tile, _)) in state.content() {
+            res.set_tile_at(p, tile);
+            if let Some(item) = state.get_item_at(p) {
+                res.insert_item(p, item);
+            }
+            if let Some(item) = state.get_placed_at(p) {
+                res.set_placed_at(p, item);
+            }
+        }
+        let mut buns = state.buns().to_vec();
+        buns.reverse();
+        res.set_buns(buns);
+        res.set_paquerette(state.get_paquerette());
+        res
+    }
+
+    #[test]
+    fn test_zobrist_incremental() {
+        let mut state = level(&[
+            "###############",
+            "#P%.b.........#",
+            "#.............#",
+            "#..b.......o..#",
+            "#.............#",
+            "#.............#",
+            "#.............#",
+            "#.............#",
+        ]);
+        state.add_inventory([1, 1, 1, 1]);
+        let start = state.zobrist();
+        assert_eq!(start, rebuilt(&state).zobrist());
+        for action in [
+            Action::Use(Item::Pickaxe, Direction::Right),
+            Action::Use(Item::Shovel, Direction::Down),
+            Action::Move(Direction::Right),
+            Action::Use(Item::Trap, Direction::Down),
+            Action::Use(Item::Carrot, Direction::Right),
+        ] {
+            state.apply(action).unwrap();
+            assert_ne!(state.zobrist(), start);
+            assert_eq!(state.zobrist(), rebuilt(&state).zobrist());
+        }
+        assert_eq!(LevelState::new().zobrist(), 0);
+    }
+
+    #[test]
+    fn test_buns_are_interchangeable() {
+        let state = level(&[
+            "###############",
+            "#P..b.........#",
+            "#.............#",
+            "#..b......b...#",
+            "#.............#",
+            "#.............#",
+            "#.............#",
+            "#.............#",
+        ]);
+        let mut permuted = state.clone();
+        let mut buns = state.buns().to_vec();
+        buns.rotate_left(1);
+        permuted.set_buns(buns);
+        assert_ne!(permuted.buns(), state.buns());
+        assert_eq!(permuted, state);
+        assert_eq!(permuted.zobrist(), state.zobrist());
+
+        let mut sorted = state.buns().to_vec();
+        sorted.sort_unstable();
+        assert_eq!(permuted.sorted_buns().collect::<Vec<_>>(), sorted);
+
+        // caught buns are all the same
+        let bun = state.buns()[1];
+        permuted.set_buns(vec![BunState::Caught, bun, BunState::Caught]);
+        let mut other = state.clone();
+        other.set_buns(vec![bun, BunState::Caught, BunState::Caught]);
+        assert_eq!(permuted, other);
+        other.set_buns(vec![bun, BunState::Caught, state.buns()[2]]);
+        assert_ne!(permuted, other);
     }
 }
@@ -1,4 +1,8 @@
-use super::level_state::LevelState;
+use super::{
+    grid::{GroundTile, LEVEL_HEIGHT, LEVEL_WIDTH},
+    level_state::LevelState,
+    Position,
+};
 
 // macro_rules! level_path {
 //     ($rel_path:literal) => {
@@ -12,3 +16,64 @@ use super::level_state::LevelState;
 //         .expect("valid");
 //     res
 // }
+
+/// An empty level surrounded by walls, with Paquerette entering in the top left corner
+pub fn walled() -> LevelState {
+    let mut res = LevelState::new();
+    for p in Position::iter() {
+        let border =
+            p.x() == 0 || p.y() == 0 || p.x() == LEVEL_WIDTH - 1 || p.y() == LEVEL_HEIGHT - 1;
+        if border {
+            res.set_tile_at(
+                p,
+                GroundTile::Wall {
+                    breakable: false,
+                    tunnels: Default::default(),
+                },
+            );
+        }
+    }
+    let entry = Position::try_from((1, 1)).unwrap();
+    res.set_tile_at(entry, GroundTile::Floor { is_entry: true });
+    res.set_paquerette(entry);
+    res
+}
+
+/// Build the `.level` data of a character map, for tests.
+/// `=` and `|` are horizontal and vertical tunnels.
+/// Only the rows from the top that matter need to be given, and only up to their last
+/// tile that isn't a wall: the rest of the 15x9 level is filled with walls.
+#[cfg(test)]
+pub fn level_data(rows: &[&str]) -> String {
+    assert!(rows.len() <= LEVEL_HEIGHT as usize, "too many rows");
+    let data: Vec<String> = (0..LEVEL_HEIGHT as usize)
+        .map(|y| {
+            let row = rows.get(y).copied().unwrap_or_default();
+            assert!(row.len() <= LEVEL_WIDTH as usize, "row {y} is too long");
+            row.chars()
+                .chain(std::iter::repeat('#'))
+                .take(LEVEL_WIDTH as usize)
+                .map(|c| match c {
+                    '#' => "W",
+                    '%' => "R",
+                    '=' => "Wlr",
+                    '|' => "Wud",
+                    'o' => "E",
+                    'P' => "S",
+                    'b' => "B",
+                    _ => "T",
+                })
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+        .collect();
+    data.join("\n")
+}
+
+/// Build a level from a character map, for tests, see [`level_data`]
+#[cfg(test)]
+pub fn level(rows: &[&str]) -> LevelState {
+    let mut res = LevelState::new();
+    res.parse_level(&level_data(rows)).expect("valid level");
+    res
+}
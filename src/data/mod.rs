@@ -4,6 +4,7 @@ pub mod levels;
 pub mod position;
 pub mod item;
 pub mod world;
+pub mod zobrist;
 
 pub use self::level_state::LevelState;
 pub use self::position::Position;
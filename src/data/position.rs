@@ -1,8 +1,12 @@
+use serde::{Deserialize, Serialize};
 use std::ops::{Add, AddAssign};
 
 use super::grid::{Direction, LEVEL_HEIGHT, LEVEL_WIDTH};
 
-#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Default, Clone, Copy)]
+/// Serialized as `{"y": row, "x": column}`, counting from 0 at the top left
+#[derive(
+    Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Default, Clone, Copy, Serialize, Deserialize,
+)]
 pub struct Position {
     y: i8,
     x: i8,
@@ -24,6 +28,20 @@ impl Position {
     pub fn is_inner(&self) -> bool {
         self.x >= 0 && self.y >= 0 && self.x < LEVEL_WIDTH && self.y < LEVEL_HEIGHT
     }
+
+    /// Row-major index of an inner position, counting from 0
+    pub fn to_index(self) -> usize {
+        debug_assert!(self.is_inner());
+        self.y as usize * LEVEL_WIDTH as usize + self.x as usize
+    }
+
+    /// Inverse of [`Self::to_index`]
+    pub fn from_index(index: usize) -> Position {
+        Position {
+            x: (index % LEVEL_WIDTH as usize) as i8,
+            y: (index / LEVEL_WIDTH as usize) as i8,
+        }
+    }
 }
 
 /// Iterate over inner positions of a level
@@ -120,6 +138,21 @@ impl Position {
     }
 }
 
+impl Position {
+    /// The position on the opposite edge, where stepping over the edge in direction `d` arrives
+    /// in the neighbouring level
+    pub fn across_edge(self, d: Direction) -> Position {
+        let mut res = self;
+        match d {
+            Direction::Up => res.y = LEVEL_HEIGHT - 1,
+            Direction::Down => res.y = 0,
+            Direction::Left => res.x = LEVEL_WIDTH - 1,
+            Direction::Right => res.x = 0,
+        }
+        res
+    }
+}
+
 impl Add<Direction> for Position {
     type Output = Position;
 
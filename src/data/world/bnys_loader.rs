@@ -1,4 +1,4 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 use std::{fs::File, path::Path};
 
@@ -42,7 +42,7 @@ struct BnysLevel {
     tools: BnysTools,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Deserialize, Serialize, Default)]
 #[serde(rename_all = "PascalCase", deny_unknown_fields, default)]
 struct BnysTools {
     traps: u8,
@@ -51,6 +51,44 @@ struct BnysTools {
     shovels: u8,
 }
 
+/// Name and tools of a level, as in the `.json` file next to its `.level` file
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct LevelInfo {
+    pub name: String,
+    pub tools: [u8; Item::COUNT],
+}
+
+pub fn read_level_info(path: impl AsRef<Path>) -> Result<LevelInfo> {
+    let path = path.as_ref();
+    let json = std::fs::read_to_string(path)
+        .with_context(|| format!("Accessing file {}.", path.display()))?;
+    let json: BnysLevel =
+        serde_json::from_str(&json).with_context(|| format!("Parsing file {}.", path.display()))?;
+    Ok(LevelInfo {
+        name: json.name,
+        tools: tools_to_array(&json.tools),
+    })
+}
+
+/// Write name and tools to the `.json` file of a level, keeping its other fields
+pub fn write_level_info(path: impl AsRef<Path>, info: &LevelInfo) -> Result<()> {
+    let path = path.as_ref();
+    let mut json = Map::new();
+    if path.exists() {
+        let old = std::fs::read_to_string(path)
+            .with_context(|| format!("Accessing file {}.", path.display()))?;
+        json = serde_json::from_str(&old)
+            .with_context(|| format!("Parsing file {}.", path.display()))?;
+    }
+    json.insert("Name".to_owned(), Value::String(info.name.clone()));
+    json.insert(
+        "Tools".to_owned(),
+        serde_json::to_value(array_to_tools(info.tools))?,
+    );
+    let json = serde_json::to_string_pretty(&json)?;
+    std::fs::write(path, json).with_context(|| format!("Writing file {}.", path.display()))
+}
+
 pub fn load_worlds(path: impl AsRef<Path>) -> Result<Vec<World>> {
     let path: &Path = path.as_ref();
     let dir = path
@@ -75,14 +113,11 @@ fn load_world(world_dir: impl AsRef<Path>) -> Result<World> {
         .and_then(serde_json::from_value)
         .with_context(|| format!("Parsing {}.", config_path.display()))?;
 
-    let mut burrows: Vec<Rc<RefCell<Burrow>>> = Vec::new();
-    burrows.resize_with(config.burrows.len(),Default::default);
-
-    // get names for backlinks
-    let mut burrows_by_name: HashMap<String, Weak<RefCell<Burrow>>> = Default::default();
-    for (rc, burrow) in std::iter::zip(burrows.iter(), config.burrows.iter()) {
+    // get ids for links
+    let mut burrows_by_name: HashMap<String, BurrowId> = Default::default();
+    for (index, burrow) in config.burrows.iter().enumerate() {
         let inserted = burrows_by_name
-            .insert(burrow.name.clone(), Rc::downgrade(rc))
+            .insert(burrow.name.clone(), BurrowId(index))
             .is_none();
         if !inserted {
             return Err(anyhow!(
@@ -94,17 +129,23 @@ fn load_world(world_dir: impl AsRef<Path>) -> Result<World> {
     }
 
     // fill burrow data
-    for (rc, burrow) in std::iter::zip(burrows.iter(), config.burrows.iter()) {
-        let mut to_fill = RefCell::borrow_mut(rc);
-
-        *to_fill = Burrow {
+    let mut burrows = Vec::with_capacity(config.burrows.len());
+    for burrow in &config.burrows {
+        burrows.push(Burrow {
+            name: burrow.name.clone(),
             has_surface_entry: burrow.has_surface_entry,
-            links: links_to_array(&burrow.links, &burrows_by_name)?,
+            links: links_to_array(&burrow.links, &burrows_by_name).with_context(|| {
+                format!(
+                    "Linking burrow {} in {}",
+                    burrow.name,
+                    config_path.display()
+                )
+            })?,
             levels: load_levels(
                 world_dir.join(burrow.directory.clone()).as_path(),
                 burrow.depth,
             )?,
-        }
+        })
     }
 
     Ok(World {
@@ -137,19 +178,17 @@ fn load_levels(level_dir: impl AsRef<Path>, depth: usize) -> Result<Vec<Option<L
                 "Could only find one of the files {} and {}.",
                 path_json.display(),
                 path_level.display()
-            ))?;
+            ));
         }
-        let json = std::fs::read_to_string(&path_json)
-            .with_context(|| format!("Accessing file {}.", path_json.display()))?;
-        let json: BnysLevel = serde_json::from_str(&json)
-            .with_context(|| format!("Parsing file {}.", path_json.display()))?;
+        let info = read_level_info(&path_json)?;
         let level_data = std::fs::read_to_string(&path_level)
             .with_context(|| format!("Accessing file {}.", path_level.display()))?;
 
         *level = Some(LevelTemplate {
-            name: json.name,
+            name: info.name,
+            path: path_level,
             data: level_data,
-            tools: tools_to_array(&json.tools),
+            tools: info.tools,
         })
         //let Ok(str = std::fs::read_to_string(level_dir.join(format!("{id}.level")))
     }
@@ -158,9 +197,9 @@ fn load_levels(level_dir: impl AsRef<Path>, depth: usize) -> Result<Vec<Option<L
 
 fn links_to_array(
     links: &BnysLinks,
-    burrows_by_name: &HashMap<String, Weak<RefCell<Burrow>>>,
-) -> Result<[Option<Weak<RefCell<Burrow>>>; Direction::COUNT]> {
-    let mut res: [Option<Weak<RefCell<Burrow>>>; Direction::COUNT] = Default::default();
+    burrows_by_name: &HashMap<String, BurrowId>,
+) -> Result<[Option<BurrowId>; Direction::COUNT]> {
+    let mut res: [Option<BurrowId>; Direction::COUNT] = Default::default();
     let mut work = |dir, link: &str| {
         let target = burrows_by_name.get(link);
         let Some(target) = target else {
@@ -169,7 +208,7 @@ fn links_to_array(
             }
             return Ok(());
         };
-        res[dir as usize] = Some(target.clone());
+        res[dir as usize] = Some(*target);
         Ok(())
     };
     work(Direction::Left, &links.left)?;
@@ -190,3 +229,49 @@ fn tools_to_array(tools: &BnysTools) -> [u8; Item::COUNT] {
     work(Item::Shovel, tools.shovels);
     res
 }
+
+fn array_to_tools(tools: [u8; Item::COUNT]) -> BnysTools {
+    BnysTools {
+        traps: tools[Item::Trap],
+        pickaxes: tools[Item::Pickaxe],
+        carrots: tools[Item::Carrot],
+        shovels: tools[Item::Shovel],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_links_resolve() {
+        let burrows_by_name = HashMap::from([("A".to_owned(), BurrowId(0))]);
+        let mut links = BnysLinks {
+            left: "A".to_owned(),
+            right: "__UNLINKED__".to_owned(),
+            ..Default::default()
+        };
+        let res = links_to_array(&links, &burrows_by_name).unwrap();
+        assert_eq!(res[Direction::Left], Some(BurrowId(0)));
+        assert_eq!(res[Direction::Right], None);
+
+        links.up = "B".to_owned();
+        assert!(links_to_array(&links, &burrows_by_name).is_err());
+    }
+
+    #[test]
+    fn test_level_info_keeps_other_fields() {
+        let path = std::env::temp_dir().join(format!("mech-bun-{}.json", std::process::id()));
+        std::fs::write(&path, r#"{"Name": "Old", "Hint": "Dig"}"#).unwrap();
+        let info = LevelInfo {
+            name: "New".to_owned(),
+            tools: [1, 0, 2, 0],
+        };
+        write_level_info(&path, &info).unwrap();
+        let read = read_level_info(&path);
+        let json = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read.unwrap(), info);
+        assert!(json.contains("\"Hint\": \"Dig\""));
+    }
+}
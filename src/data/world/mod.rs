@@ -1,62 +1,873 @@
 pub mod bnys_loader;
 
 use std::{
-    cell::RefCell,
     collections::HashMap,
-    rc::{Rc, Weak},
+    fmt,
+    path::{Path, PathBuf},
 };
 
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
+use serde::{
+    de::{self, DeserializeSeed, Deserializer},
+    Deserialize, Serialize, Serializer,
+};
+
+use super::{
+    item::serde_inventory,
+    level_state::{Action, BunState, LevelExit, MoveError, ParseError},
+    zobrist, Direction, Item, LevelState, Position,
+};
 
-use super::{Direction, Item, LevelState};
+use strum::{EnumCount, IntoEnumIterator};
 
-use strum::EnumCount;
+/// Index of a burrow in its [`World`]
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct BurrowId(usize);
+
+/// A level of a [`World`], by burrow and depth
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct LevelId {
+    pub burrow: BurrowId,
+    /// starting with 1 for the level at the top
+    pub depth: usize,
+}
 
 #[derive(Debug)]
 pub struct World {
     title: String,
-    burrows: Vec<Rc<RefCell<Burrow>>>,
+    burrows: Vec<Burrow>,
 }
 
 #[derive(Debug, Default)]
 struct Burrow {
+    name: String,
     has_surface_entry: bool,
-    links: [Option<Weak<RefCell<Burrow>>>; Direction::COUNT],
+    /// the burrows next to this one
+    links: [Option<BurrowId>; Direction::COUNT],
     levels: Vec<Option<LevelTemplate>>, // indexing starts with 1, so 0 is always supposed to be None,
 }
 
 #[derive(Debug)]
 struct LevelTemplate {
     name: String,
+    /// the `.level` file the data was read from
+    path: PathBuf,
     data: String,
     tools: [u8; Item::COUNT],
 }
 
+/// A level of a [`World`] that doesn't parse
+#[derive(Debug, Clone)]
+pub struct LevelError {
+    pub burrow: String,
+    pub depth: usize,
+    /// the `.level` file of the level
+    pub path: PathBuf,
+    pub error: ParseError,
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({} {}): {}",
+            self.path.display(),
+            self.burrow,
+            self.depth,
+            self.error
+        )
+    }
+}
+
+impl std::error::Error for LevelError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[derive(Clone)]
 pub struct WorldState<'a> {
     world: &'a World,
-    borrow: Rc<RefCell<Burrow>>,
-    depth: usize,
+    level: LevelId,
+    /// inventory Paquerette brought into the current level
+    carried: [u8; Item::COUNT],
+    /// levels other than the current one,
+    /// as they were left or as escaped buns arrived in them
+    levels: HashMap<LevelId, LevelState>,
     pub level_state: super::LevelState,
+    /// the current level as Paquerette entered it, to restart from
+    entered: LevelState,
+    /// buns escaped from the current level since it was entered, with where they arrived
+    delivered: Vec<(LevelId, Position)>,
 }
 
 impl World {
-    pub fn enter(&self) -> anyhow::Result<WorldState> {
-        for rc in &self.burrows {
-            let b = rc.borrow();
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn burrow_names(&self) -> Vec<String> {
+        self.burrows.iter().map(|b| b.name.clone()).collect()
+    }
+
+    pub fn burrow_id(&self, name: &str) -> Option<BurrowId> {
+        self.burrows.iter().position(|b| b.name == name).map(BurrowId)
+    }
+
+    pub fn burrow_name(&self, id: BurrowId) -> &str {
+        &self.burrows[id.0].name
+    }
+
+    /// The burrow next to `id` in direction `d`
+    pub fn link(&self, id: BurrowId, d: Direction) -> Option<BurrowId> {
+        self.burrows[id.0].links[d]
+    }
+
+    /// The level of the burrow named `burrow` at `depth`, if there is one
+    pub fn level_id(&self, burrow: &str, depth: usize) -> Option<LevelId> {
+        let id = LevelId {
+            burrow: self.burrow_id(burrow)?,
+            depth,
+        };
+        self.template(id).map(|_| id)
+    }
+
+    /// Depths and names of the levels of a burrow
+    pub fn level_names(&self, burrow: &str) -> Vec<(usize, String)> {
+        let Some(id) = self.burrow_id(burrow) else {
+            return Vec::new();
+        };
+        self.burrows[id.0]
+            .levels
+            .iter()
+            .enumerate()
+            .filter_map(|(depth, l)| Some((depth, l.as_ref()?.name.clone())))
+            .collect()
+    }
+
+    /// Every level of the world
+    pub fn level_ids(&self) -> Vec<LevelId> {
+        let mut res = Vec::new();
+        for (index, b) in self.burrows.iter().enumerate() {
+            for (depth, l) in b.levels.iter().enumerate() {
+                if l.is_some() {
+                    res.push(LevelId {
+                        burrow: BurrowId(index),
+                        depth,
+                    });
+                }
+            }
+        }
+        res
+    }
+
+    /// The buns a level starts with, 0 if it doesn't parse
+    pub fn bun_count(&self, id: LevelId) -> usize {
+        match self.parse_level(id) {
+            Some(Ok(state)) => state.buns().len(),
+            _ => 0,
+        }
+    }
+
+    /// Parse every level, returning the errors of the broken ones
+    pub fn level_errors(&self) -> Vec<LevelError> {
+        self.level_ids()
+            .into_iter()
+            .filter_map(|id| self.load(id)?.err())
+            .collect()
+    }
+
+    pub fn load_level(&self, burrow: &str, depth: usize) -> anyhow::Result<LevelState> {
+        let id = LevelId {
+            burrow: self
+                .burrow_id(burrow)
+                .ok_or_else(|| anyhow!("Unknown burrow {burrow}"))?,
+            depth,
+        };
+        Ok(self
+            .load(id)
+            .ok_or_else(|| anyhow!("No level {burrow} {depth}"))??)
+    }
+
+    pub fn enter(&self) -> anyhow::Result<WorldState<'_>> {
+        for (index, b) in self.burrows.iter().enumerate() {
             if b.levels.len() <= 1 || b.levels[1].is_none() {
                 continue;
             }
             if b.has_surface_entry {
-                let mut state = LevelState::new();
-                state.parse_level(&b.levels[1].as_ref().unwrap().data).map_err(|_|anyhow!("Parsing error"));
-                return Ok(WorldState {
-                    world: self,
-                    borrow: rc.clone(),
+                return self.state_at(LevelId {
+                    burrow: BurrowId(index),
                     depth: 1,
-                    level_state: state,
                 });
             }
         }
         Err(anyhow!("Could not find borrow accessible from top"))
     }
+
+    /// Start playing at the level of the burrow named `burrow` at `depth`
+    pub fn enter_at(&self, burrow: &str, depth: usize) -> anyhow::Result<WorldState<'_>> {
+        let burrow = self
+            .burrow_id(burrow)
+            .ok_or_else(|| anyhow!("Unknown burrow {burrow}"))?;
+        self.state_at(LevelId { burrow, depth })
+    }
+
+    /// Resume playing from a file written by [`WorldState::save`]
+    pub fn load_state(&self, path: &Path) -> anyhow::Result<WorldState<'_>> {
+        let json =
+            std::fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+        let mut deserializer = serde_json::Deserializer::from_str(&json);
+        self.deserialize(&mut deserializer)
+            .with_context(|| format!("Loading {}", path.display()))
+    }
+
+    fn state_at(&self, level: LevelId) -> anyhow::Result<WorldState<'_>> {
+        let name = self.burrow_name(level.burrow);
+        let depth = level.depth;
+        let level_state = self
+            .load(level)
+            .ok_or_else(|| anyhow!("No level {name} {depth}"))??;
+        Ok(WorldState {
+            world: self,
+            level,
+            carried: Default::default(),
+            levels: HashMap::new(),
+            entered: level_state.clone(),
+            delivered: Vec::new(),
+            level_state,
+        })
+    }
+}
+
+impl World {
+    fn template(&self, id: LevelId) -> Option<&LevelTemplate> {
+        self.burrows.get(id.burrow.0)?.levels.get(id.depth)?.as_ref()
+    }
+
+    fn parse_level(&self, id: LevelId) -> Option<Result<LevelState, ParseError>> {
+        let template = self.template(id)?;
+        let mut state = LevelState::new();
+        state.set_inventory(template.tools);
+        Some(state.parse_level(&template.data).map(|()| state))
+    }
+
+    /// Like [`Self::parse_level`], with errors telling which level is broken
+    fn load(&self, id: LevelId) -> Option<Result<LevelState, LevelError>> {
+        let res = self.parse_level(id)?.map_err(|error| LevelError {
+            burrow: self.burrow_name(id.burrow).to_owned(),
+            depth: id.depth,
+            path: self.template(id).expect("parsed").path.clone(),
+            error,
+        });
+        Some(res)
+    }
+}
+
+impl<'a> WorldState<'a> {
+    pub fn world(&self) -> &'a World {
+        self.world
+    }
+
+    /// Write the state to `path` as JSON, see [`World::load_state`].
+    /// The file is replaced at once, so a failed save keeps the previous one.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, json).with_context(|| format!("Writing {}", path.display()))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("Writing {}", path.display()))
+    }
+
+    pub fn burrow_name(&self) -> String {
+        self.world.burrow_name(self.level.burrow).to_owned()
+    }
+
+    pub fn depth(&self) -> usize {
+        self.level.depth
+    }
+
+    /// The level Paquerette is in
+    pub fn level(&self) -> LevelId {
+        self.level
+    }
+
+    /// The buns caught so far in the whole world
+    pub fn caught_buns(&self) -> usize {
+        std::iter::once(&self.level_state)
+            .chain(self.levels.values())
+            .flat_map(|l| l.buns())
+            .filter(|&&b| b == BunState::Caught)
+            .count()
+    }
+
+    /// Zobrist key of the whole state, combining the keys of its levels
+    pub fn zobrist(&self) -> u64 {
+        let level = |id: LevelId, current, state: &LevelState| {
+            zobrist::level(id.burrow.0, id.depth, current, state.zobrist())
+        };
+        let mut res = level(self.level, true, &self.level_state);
+        for (&id, state) in &self.levels {
+            res = res.wrapping_add(level(id, false, state));
+        }
+        for item in Item::iter() {
+            res = res.wrapping_add(zobrist::carried(item, self.carried[item]));
+        }
+        res
+    }
+
+    pub fn apply(&mut self, action: Action) -> Result<Vec<LevelState>, MoveError> {
+        match action {
+            Action::Move(d) => self.move_to(d),
+            Action::Use(..) => self.level_state.apply(action),
+        }
+    }
+
+    /// Move Paquerette, following exits into other levels of the world.
+    /// A move that fails leaves the state as it was.
+    pub fn move_to(&mut self, d: Direction) -> Result<Vec<LevelState>, MoveError> {
+        match self.level_state.exit_for(d) {
+            None => {
+                let mut moved = self.level_state.clone();
+                let res = moved.move_to(d)?;
+                let loaded = self.load_targets(&moved)?;
+                self.level_state = moved;
+                self.levels.extend(loaded);
+                self.deliver_escaped();
+                Ok(res)
+            }
+            Some(exit) => {
+                self.leave(exit)?;
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    /// The level an exit of the current level leads to
+    fn target(&self, exit: LevelExit) -> Option<LevelId> {
+        let LevelId { burrow, depth } = self.level;
+        let id = match exit {
+            LevelExit::Down(_) => LevelId {
+                burrow,
+                depth: depth + 1,
+            },
+            LevelExit::Up(_) => LevelId {
+                burrow,
+                depth: depth.checked_sub(1)?,
+            },
+            LevelExit::Side(_, d) => LevelId {
+                burrow: self.world.link(burrow, d)?,
+                depth,
+            },
+        };
+        self.world.template(id).map(|_| id)
+    }
+
+    /// The levels not stored yet that the escaped buns of `state` land in,
+    /// so none of them fails to load once delivering has begun
+    fn load_targets(&self, state: &LevelState) -> Result<HashMap<LevelId, LevelState>, MoveError> {
+        let mut res = HashMap::new();
+        for exit in escaped(state) {
+            let Some(target) = self.target(exit) else {
+                continue;
+            };
+            if !self.levels.contains_key(&target) && !res.contains_key(&target) {
+                let level = self.world.parse_level(target).expect("target level");
+                let level = level.map_err(MoveError::InvalidLevel)?;
+                // a level no bun lands in stays unstored, as it was
+                if !level.is_solid(exit.arrival()) {
+                    res.insert(target, level);
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    /// Move buns that escaped the current level into the levels their exits lead to,
+    /// which have to be stored, see [`Self::load_targets`]
+    fn deliver_escaped(&mut self) {
+        let mut delivered = Vec::new();
+        for exit in escaped(&self.level_state) {
+            // without a level or a free tile behind the exit, the bun stays escaped
+            let Some(target) = self.target(exit) else {
+                continue;
+            };
+            let Some(level) = self.levels.get_mut(&target) else {
+                continue;
+            };
+            if level.is_solid(exit.arrival()) {
+                continue;
+            }
+            level.set_bunny(exit.arrival());
+            self.delivered.push((target, exit.arrival()));
+            delivered.push(exit);
+        }
+        self.level_state
+            .release_escaped(|exit| delivered.contains(&exit));
+    }
+
+    /// Put the current level back the way Paquerette entered it,
+    /// taking the buns that escaped since then out of the levels they arrived in
+    pub fn restart_level(&mut self) {
+        self.level_state = self.entered.clone();
+        for (id, p) in self.delivered.drain(..) {
+            let level = self.levels.get_mut(&id).expect("delivered to stored level");
+            level.remove_bunny(p);
+        }
+    }
+
+    fn leave(&mut self, exit: LevelExit) -> Result<(), MoveError> {
+        let target = self.target(exit).ok_or(MoveError::NoLevel(exit))?;
+        let arrival = exit.arrival();
+        let mut state = match self.levels.get(&target) {
+            Some(state) => state.clone(),
+            None => self
+                .world
+                .parse_level(target)
+                .expect("target level")
+                .map_err(MoveError::InvalidLevel)?,
+        };
+        if state.is_solid(arrival) {
+            return Err(MoveError::Blocked(arrival));
+        }
+        self.levels.remove(&target);
+        state.set_paquerette(arrival);
+        // a bun that escaped to where Paquerette arrives is caught, as if she stepped onto it
+        state.catch_bunny(arrival);
+        // tools given by the level are picked up on arrival, the others are carried along
+        self.carried = self.level_state.inventory();
+        state.add_inventory(self.carried);
+        self.level_state.set_inventory(Default::default());
+        self.entered = state.clone();
+        self.delivered.clear();
+        let mut left = std::mem::replace(&mut self.level_state, state);
+        // where Paquerette was in a level doesn't matter once she is gone, she arrives anew,
+        // and a level left as it was is loaded again on entry, so equal states get equal keys
+        let template = self.world.parse_level(self.level).expect("current level");
+        let template = template.expect("parsed on entry");
+        left.set_paquerette(template.get_paquerette());
+        if left != template {
+            self.levels.insert(self.level, left);
+        }
+        self.level = target;
+        Ok(())
+    }
+}
+
+/// The exits of the buns that escaped `state`
+fn escaped(state: &LevelState) -> Vec<LevelExit> {
+    state
+        .buns()
+        .iter()
+        .filter_map(|b| match b {
+            BunState::Escaped(exit) => Some(*exit),
+            _ => None,
+        })
+        .collect()
+}
+
+/// States are equal when the game goes on the same from them,
+/// which doesn't depend on how the current level was entered
+impl PartialEq for WorldState<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.world, other.world)
+            && self.level == other.level
+            && self.carried == other.carried
+            && self.level_state == other.level_state
+            && self.levels == other.levels
+    }
+}
+
+impl Eq for WorldState<'_> {}
+
+/// The JSON form of a [`WorldState`], naming burrows so it stays valid when they are reordered
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WorldStateJson {
+    /// title of the world
+    world: String,
+    burrow: String,
+    depth: usize,
+    #[serde(with = "serde_inventory")]
+    carried: [u8; Item::COUNT],
+    level_state: LevelState,
+    /// the current level as it was entered, see [`WorldState::restart_level`]
+    entered: LevelState,
+    delivered: Vec<DeliveredBunJson>,
+    levels: Vec<StoredLevelJson>,
+    /// for readers of the JSON, recomputed from the levels when loading
+    #[serde(default)]
+    caught_buns: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StoredLevelJson {
+    burrow: String,
+    depth: usize,
+    state: LevelState,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DeliveredBunJson {
+    burrow: String,
+    depth: usize,
+    position: Position,
+}
+
+impl Serialize for WorldState<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut levels: Vec<_> = self.levels.iter().collect();
+        levels.sort_by_key(|(&id, _)| id);
+        let json = WorldStateJson {
+            world: self.world.title.clone(),
+            burrow: self.burrow_name(),
+            depth: self.depth(),
+            carried: self.carried,
+            level_state: self.level_state.clone(),
+            entered: self.entered.clone(),
+            delivered: self
+                .delivered
+                .iter()
+                .map(|&(id, position)| DeliveredBunJson {
+                    burrow: self.world.burrow_name(id.burrow).to_owned(),
+                    depth: id.depth,
+                    position,
+                })
+                .collect(),
+            levels: levels
+                .into_iter()
+                .map(|(&id, state)| StoredLevelJson {
+                    burrow: self.world.burrow_name(id.burrow).to_owned(),
+                    depth: id.depth,
+                    state: state.clone(),
+                })
+                .collect(),
+            caught_buns: self.caught_buns(),
+        };
+        json.serialize(serializer)
+    }
+}
+
+/// Deserialize a [`WorldState`] of this world
+impl<'de, 'a> DeserializeSeed<'de> for &'a World {
+    type Value = WorldState<'a>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        let json = WorldStateJson::deserialize(deserializer)?;
+        if json.world != self.title {
+            return Err(de::Error::custom(format!(
+                "state of world {}, not {}",
+                json.world, self.title
+            )));
+        }
+        let level_id = |burrow: &str, depth| {
+            self.level_id(burrow, depth)
+                .ok_or_else(|| de::Error::custom(format!("no level {burrow} {depth}")))
+        };
+        let mut levels = HashMap::new();
+        for stored in json.levels {
+            levels.insert(level_id(&stored.burrow, stored.depth)?, stored.state);
+        }
+        let mut delivered = Vec::new();
+        for bun in json.delivered {
+            let id = level_id(&bun.burrow, bun.depth)?;
+            if !levels.contains_key(&id) {
+                return Err(de::Error::custom(format!(
+                    "bun delivered to {} {}, which isn't stored",
+                    bun.burrow, bun.depth
+                )));
+            }
+            delivered.push((id, bun.position));
+        }
+        Ok(WorldState {
+            world: self,
+            level: level_id(&json.burrow, json.depth)?,
+            carried: json.carried,
+            levels,
+            level_state: json.level_state,
+            entered: json.entered,
+            delivered,
+        })
+    }
+}
+
+#[cfg(test)]
+impl World {
+    /// A world of unlinked burrows, each with levels from character maps,
+    /// see [`super::levels::level_data`]
+    pub fn from_maps(title: &str, burrows: &[(&str, &[&[&str]])]) -> World {
+        let burrows = burrows
+            .iter()
+            .map(|&(name, levels)| {
+                let mut res = Burrow {
+                    name: name.to_owned(),
+                    has_surface_entry: true,
+                    levels: vec![None],
+                    ..Default::default()
+                };
+                for (depth, rows) in levels.iter().enumerate() {
+                    res.levels.push(Some(LevelTemplate {
+                        name: format!("{name} {}", depth + 1),
+                        path: format!("{name}/{}.level", depth + 1).into(),
+                        data: super::levels::level_data(rows),
+                        tools: Default::default(),
+                    }));
+                }
+                res
+            })
+            .collect();
+        World {
+            title: title.to_owned(),
+            burrows,
+        }
+    }
+
+    /// A world of one burrow with two levels, given by their second rows,
+    /// see [`super::levels::level_data`]
+    pub fn two_levels(top: &str, below: &str) -> World {
+        let top = ["###############", top];
+        let below = ["###############", below];
+        World::from_maps("Test", &[("A", &[&top, &below])])
+    }
+
+    /// A world where Paquerette can chase a bun down a hole into the level below
+    pub fn bun_by_hole() -> World {
+        World::two_levels("#P.bo", "#...P.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use serde::de::DeserializeSeed;
+
+    use crate::data::{
+        level_state::{BunState, LevelExit, MoveError, ITEM_ATTRIBUTES, TUNNEL_ATTRIBUTES},
+        Direction, LevelState, Position,
+    };
+
+    use super::{BurrowId, LevelId, World, WorldState};
+
+    #[test]
+    fn test_down_and_up() {
+        let world = World::two_levels("#P.o", "#..P");
+        let hole = Position::try_from((3, 1)).unwrap();
+        let mut state = world.enter().unwrap();
+        state.move_to(Direction::Right).unwrap();
+        state.move_to(Direction::Right).unwrap();
+        assert_eq!(state.depth(), 2);
+        assert_eq!(state.level_state.get_paquerette(), hole);
+
+        state.move_to(Direction::Left).unwrap();
+        state.move_to(Direction::Right).unwrap();
+        assert_eq!(state.depth(), 1);
+        assert_eq!(state.level_state.get_paquerette(), hole);
+        // the level below was left as it was, like a level never entered
+        assert!(state.levels.is_empty());
+        state.move_to(Direction::Left).unwrap();
+        let mut never_below = world.enter().unwrap();
+        never_below.move_to(Direction::Right).unwrap();
+        assert_eq!(state.zobrist(), never_below.zobrist());
+        assert!(state == never_below);
+    }
+
+    #[test]
+    fn test_broken_level() {
+        let mut world = World::two_levels("#P.o", "#..P");
+        world.burrows[0].levels[2].as_mut().unwrap().data = "X".to_owned();
+        let errors = world.level_errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].burrow.as_str(), errors[0].depth), ("A", 2));
+        assert_eq!(errors[0].path, Path::new("A/2.level"));
+
+        let mut state = world.enter().unwrap();
+        state.move_to(Direction::Right).unwrap();
+        assert_eq!(
+            state.move_to(Direction::Right),
+            Err(MoveError::InvalidLevel(errors[0].error.clone()))
+        );
+        assert_eq!(state.depth(), 1);
+    }
+
+    #[test]
+    fn test_bun_falls_into_stored_level() {
+        let world = World::bun_by_hole();
+        let hole = Position::try_from((4, 1)).unwrap();
+        let mut state = world.enter().unwrap();
+        state.move_to(Direction::Right).unwrap();
+        assert!(state.level_state.buns().is_empty());
+        let below = LevelId {
+            burrow: BurrowId(0),
+            depth: 2,
+        };
+        assert_eq!(state.levels[&below].buns(), [BunState::In(hole)]);
+
+        // the level below is restored with the bun in it, caught by Paquerette landing on it
+        state.move_to(Direction::Right).unwrap();
+        state.move_to(Direction::Right).unwrap();
+        assert_eq!(state.depth(), 2);
+        assert_eq!(state.level_state.buns(), [BunState::Caught]);
+    }
+
+    #[test]
+    fn test_bun_blocked_below() {
+        let world = World::two_levels("#P.bo", "#P.");
+        let hole = Position::try_from((4, 1)).unwrap();
+        let mut state = world.enter().unwrap();
+        state.move_to(Direction::Right).unwrap();
+        // with a wall where it would land, the bun is lost like one leaving the world
+        assert_eq!(
+            state.level_state.buns(),
+            [BunState::Escaped(LevelExit::Down(hole))]
+        );
+        assert!(state.levels.is_empty());
+        assert!(state.delivered.is_empty());
+    }
+
+    #[test]
+    fn test_arrival_blocked() {
+        let world = World::two_levels("#P.o", "#P.");
+        let hole = Position::try_from((3, 1)).unwrap();
+        let mut state = world.enter().unwrap();
+        state.move_to(Direction::Right).unwrap();
+        let before = state.clone();
+        assert_eq!(
+            state.move_to(Direction::Right),
+            Err(MoveError::Blocked(hole))
+        );
+        assert!(state == before);
+        assert_eq!(state.depth(), 1);
+        assert!(state.levels.is_empty());
+    }
+
+    #[test]
+    fn test_restart_level() {
+        let world = World::two_levels("#P.bo", "#...P.b");
+        let buns = |state: &WorldState| -> usize {
+            std::iter::once(&state.level_state)
+                .chain(state.levels.values())
+                .map(|l| l.buns().len())
+                .sum()
+        };
+        let mut state = world.enter().unwrap();
+        let entered = state.level_state.clone();
+        state.move_to(Direction::Right).unwrap();
+        assert_eq!(buns(&state), 2);
+
+        // the bun chased into the level below comes back
+        state.restart_level();
+        assert_eq!(state.level_state, entered);
+        assert_eq!(buns(&state), 2);
+
+        // buns that arrived before Paquerette stay in the level she restarts
+        state.move_to(Direction::Right).unwrap();
+        state.move_to(Direction::Right).unwrap();
+        state.move_to(Direction::Right).unwrap();
+        let entered = state.level_state.clone();
+        assert_eq!(entered.buns().len(), 2);
+        state.move_to(Direction::Left).unwrap();
+        state.restart_level();
+        assert_eq!(state.level_state, entered);
+        assert_eq!(buns(&state), 2);
+    }
+
+    #[test]
+    fn test_failed_move_changes_nothing() {
+        let mut world = World::bun_by_hole();
+        // the level below, where the bun would flee to, is broken
+        world.burrows[0].levels[2].as_mut().unwrap().data = "X".to_owned();
+        let mut state = world.enter().unwrap();
+        let before = state.clone();
+        let res = state.move_to(Direction::Right);
+        assert!(matches!(res, Err(MoveError::InvalidLevel(_))));
+        assert!(state == before);
+        assert_eq!(
+            state.level_state.get_paquerette(),
+            before.level_state.get_paquerette()
+        );
+        assert_eq!(state.level_state.buns(), before.level_state.buns());
+        assert!(state.levels.is_empty());
+        assert!(state.delivered.is_empty());
+    }
+
+    /// Write every level of `worlds` as a `.level` string and check that it parses back,
+    /// returning all the strings
+    fn assert_round_trips(worlds: &[World]) -> String {
+        let mut written = String::new();
+        for world in worlds {
+            for id in world.level_ids() {
+                let state = world.parse_level(id).expect("listed level").unwrap();
+                let data = state.to_level_string().expect("unplayed level");
+                let mut parsed = LevelState::new();
+                parsed.set_inventory(state.inventory());
+                parsed.parse_level(&data).unwrap();
+                assert_eq!(parsed, state, "{} {:?}", world.title(), id);
+                written += &data;
+            }
+        }
+        written
+    }
+
+    /// Every level of the fixture world parses back from its `.level` string,
+    /// and together they use every tile and attribute token
+    #[test]
+    fn test_level_strings_round_trip() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
+        let worlds = super::bnys_loader::load_worlds(&dir).unwrap();
+        assert!(!worlds.is_empty());
+        let written = assert_round_trips(&worlds);
+        let tokens = "WRTESB".chars();
+        let attributes = TUNNEL_ATTRIBUTES.map(|(a, _)| a).into_iter();
+        let attributes = attributes.chain(ITEM_ATTRIBUTES.map(|(a, _)| a));
+        for c in tokens.chain(attributes) {
+            assert!(written.contains(c), "no `{c}` in the fixture");
+        }
+    }
+
+    /// Every level of the worlds in `./burrows`, or in the directory `BURROWS` names,
+    /// parses back from its `.level` string.
+    /// The worlds aren't in the repository, run with `cargo test -- --ignored` where they are.
+    #[test]
+    #[ignore = "needs the worlds in ./burrows or the directory BURROWS names"]
+    fn test_burrows_round_trip() {
+        let dir = match std::env::var_os("BURROWS") {
+            Some(dir) => PathBuf::from(dir),
+            None => Path::new(env!("CARGO_MANIFEST_DIR")).join("burrows"),
+        };
+        let worlds = super::bnys_loader::load_worlds(&dir).unwrap();
+        assert!(!worlds.is_empty(), "no worlds in {}", dir.display());
+        assert_round_trips(&worlds);
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let world = World::bun_by_hole();
+        let mut state = world.enter().unwrap();
+        state.move_to(Direction::Right).unwrap();
+        let json = serde_json::to_string(&state).unwrap();
+        let mut deserializer = serde_json::Deserializer::from_str(&json);
+        let loaded = (&world).deserialize(&mut deserializer).unwrap();
+        assert_eq!(loaded.level(), state.level());
+        assert_eq!(loaded.zobrist(), state.zobrist());
+        assert_eq!(loaded.levels.len(), 1);
+
+        let other = World::from_maps("Other", &[]);
+        let mut deserializer = serde_json::Deserializer::from_str(&json);
+        assert!((&other).deserialize(&mut deserializer).is_err());
+
+        let path = std::env::temp_dir().join(format!("mech-bun-{}.save.json", std::process::id()));
+        state.save(&path).unwrap();
+        let loaded = world.load_state(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap().zobrist(), state.zobrist());
+    }
+
+    #[test]
+    fn test_shareable_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<World>();
+        assert_send_sync::<WorldState<'_>>();
+    }
 }
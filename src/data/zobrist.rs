@@ -0,0 +1,102 @@
+//! Zobrist keys for the parts of a [`LevelState`](super::LevelState).
+//!
+//! The key of a state is the wrapping sum of the keys of its parts, so it can be
+//! updated incrementally whenever a part changes, regardless of the order of the buns.
+//! Unlike xor, a sum doesn't let two buns on the same tile cancel out.
+//! Default parts have the key 0, which makes `LevelState::default()` have the key 0 as well.
+
+use super::{
+    grid::GroundTile,
+    level_state::{BunState, LevelExit},
+    Item, Position,
+};
+
+const PAQUERETTE: u64 = 1;
+const TILE: u64 = 2;
+const BUN: u64 = 3;
+const ITEM: u64 = 4;
+const PLACED: u64 = 5;
+const INVENTORY: u64 = 6;
+const LEVEL: u64 = 7;
+const CARRIED: u64 = 8;
+
+/// splitmix64, a cheap bijective mix good enough to act as a table of random keys
+fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn key(part: u64, a: usize, b: usize) -> u64 {
+    mix(part << 48 | (a as u64) << 16 | b as u64)
+}
+
+pub fn paquerette(p: Position) -> u64 {
+    if p == Position::default() {
+        0
+    } else {
+        key(PAQUERETTE, p.to_index(), 0)
+    }
+}
+
+pub fn tile(p: Position, tile: GroundTile) -> u64 {
+    let kind = match tile {
+        GroundTile::Floor { is_entry: false } => return 0,
+        GroundTile::Floor { is_entry: true } => 1,
+        GroundTile::Hole => 2,
+        GroundTile::Wall { breakable, tunnels } => {
+            let openings = tunnels
+                .iter()
+                .enumerate()
+                .fold(0, |acc, (d, &open)| acc | (open as usize) << d);
+            3 + ((breakable as usize) << 4 | openings)
+        }
+    };
+    key(TILE, p.to_index(), kind)
+}
+
+/// The key of one bun, to be added to the key of the state
+pub fn bun(bun: BunState) -> u64 {
+    let (kind, p) = match bun {
+        BunState::In(p) => (0, p),
+        BunState::Caught => (1, Position::default()),
+        BunState::Escaped(LevelExit::Down(p)) => (2, p),
+        BunState::Escaped(LevelExit::Up(p)) => (3, p),
+        BunState::Escaped(LevelExit::Side(p, d)) => (4 + d as usize, p),
+    };
+    key(BUN, p.to_index(), kind)
+}
+
+/// An item lying around at `p`
+pub fn item(p: Position, item: Item) -> u64 {
+    key(ITEM, p.to_index(), item as usize)
+}
+
+/// A trap or carrot put down at `p`
+pub fn placed(p: Position, item: Item) -> u64 {
+    key(PLACED, p.to_index(), item as usize)
+}
+
+pub fn inventory(item: Item, count: u8) -> u64 {
+    if count == 0 {
+        0
+    } else {
+        key(INVENTORY, item as usize, count as usize)
+    }
+}
+
+/// A level of a world, combined with the key of its state;
+/// `current` for the level Paquerette is in
+pub fn level(burrow: usize, depth: usize, current: bool, state: u64) -> u64 {
+    mix(key(LEVEL, burrow, depth << 1 | current as usize) ^ state)
+}
+
+/// Items Paquerette brought into the current level of a world
+pub fn carried(item: Item, count: u8) -> u64 {
+    if count == 0 {
+        0
+    } else {
+        key(CARRIED, item as usize, count as usize)
+    }
+}
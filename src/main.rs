@@ -1,19 +1,12 @@
 #![allow(unused)]
-use data::world;
+use std::process::ExitCode;
 
+mod cli;
 mod data;
+mod serve;
+mod solver;
 mod tui;
 
-fn main() -> anyhow::Result<()> {
-    let dir_worlds = std::env::current_dir().unwrap().join("burrows");
-    let worlds = world::bnys_loader::load_worlds(dir_worlds)?;
-    let world = &worlds[0];
-
-    let mut state = world.enter()?;
-
-    let _ = tui::run_world(&mut state);
-
-    // let mut state = data::levels::level();
-    // let _ = tui::run_level(&mut state);
-    Ok(())
+fn main() -> ExitCode {
+    cli::run(std::env::args().skip(1))
 }
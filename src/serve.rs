@@ -0,0 +1,298 @@
+use std::{
+    io::{BufRead, Write},
+    path::PathBuf,
+};
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    data::{
+        level_state::Action,
+        world::{bnys_loader, World},
+        Direction, Item, LevelState,
+    },
+    solver::{self, Search, Solution},
+};
+
+/// A line of input of [`serve`]
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum Request {
+    /// Start playing a level, dropping the one played before
+    Load {
+        /// the fields of a [`Source`], told apart in [`Source::from_fields`]
+        #[serde(flatten)]
+        source: serde_json::Map<String, Value>,
+    },
+    Move {
+        direction: Direction,
+    },
+    UseItem {
+        item: Item,
+        direction: Direction,
+    },
+    /// Take back the last move or use of an item
+    Undo,
+    State,
+    /// Search for a solution from the current state, without changing it
+    Solve,
+}
+
+/// Where a level is loaded from
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Source {
+    World {
+        world: String,
+        burrow: String,
+        depth: usize,
+    },
+    File {
+        path: PathBuf,
+    },
+    State {
+        state: LevelState,
+    },
+}
+
+impl Source {
+    /// The source given by the fields of a `load` request, picked by the field naming it,
+    /// so a malformed request is told what is wrong with it instead of that no source matches
+    fn from_fields(fields: serde_json::Map<String, Value>) -> anyhow::Result<Source> {
+        let variant = if fields.contains_key("world") {
+            "world"
+        } else if fields.contains_key("path") {
+            "file"
+        } else if fields.contains_key("state") {
+            "state"
+        } else {
+            return Err(anyhow!("Missing `world`, `path` or `state` to load"));
+        };
+        serde_json::from_value(json!({ variant: fields }))
+            .with_context(|| format!("Invalid {variant} to load"))
+    }
+}
+
+/// A line of output of [`serve`], the fields not applying to the request are left out
+#[derive(Debug, Default, Serialize)]
+struct Response {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// the state after the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state: Option<LevelState>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    solved: Option<bool>,
+    /// the animation frames of a move, leading to the state
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    frames: Vec<LevelState>,
+    /// the solution found by `solve`, `null` if the level is unsolvable
+    #[serde(skip_serializing_if = "Option::is_none")]
+    solution: Option<Option<Solution>>,
+}
+
+struct Server {
+    burrows: PathBuf,
+    search: Search,
+    check_collisions: bool,
+    /// the worlds of the burrows directory, read on first use
+    worlds: Option<Vec<World>>,
+    state: Option<LevelState>,
+    undo_stack: Vec<LevelState>,
+}
+
+impl Server {
+    fn handle(&mut self, request: Request) -> anyhow::Result<Response> {
+        let mut frames = Vec::new();
+        let mut solution = None;
+        match request {
+            Request::Load { source } => {
+                self.state = Some(self.load(Source::from_fields(source)?)?);
+                self.undo_stack.clear();
+            }
+            Request::Move { direction } => frames = self.apply(Action::Move(direction))?,
+            Request::UseItem { item, direction } => {
+                frames = self.apply(Action::Use(item, direction))?
+            }
+            Request::Undo => {
+                let previous = self.undo_stack.pop().ok_or(anyhow!("Nothing to undo"))?;
+                self.state = Some(previous);
+            }
+            Request::State => (),
+            Request::Solve => {
+                let state = self.state()?;
+                solution = Some(solver::solve(state, self.search, self.check_collisions)?);
+            }
+        }
+        let state = self.state()?;
+        Ok(Response {
+            ok: true,
+            solved: Some(state.is_solved()),
+            state: Some(state.clone()),
+            frames,
+            solution,
+            ..Default::default()
+        })
+    }
+
+    fn state(&self) -> anyhow::Result<&LevelState> {
+        self.state.as_ref().ok_or(anyhow!("No level loaded"))
+    }
+
+    fn apply(&mut self, action: Action) -> anyhow::Result<Vec<LevelState>> {
+        let mut next = self.state()?.clone();
+        let frames = next.apply(action)?;
+        self.undo_stack.extend(self.state.replace(next));
+        Ok(frames)
+    }
+
+    fn load(&mut self, source: Source) -> anyhow::Result<LevelState> {
+        match source {
+            Source::World {
+                world,
+                burrow,
+                depth,
+            } => {
+                if self.worlds.is_none() {
+                    self.worlds = Some(bnys_loader::load_worlds(&self.burrows)?);
+                }
+                let worlds = self.worlds.as_deref().unwrap_or_default();
+                let world = worlds
+                    .iter()
+                    .find(|w| w.title() == world)
+                    .ok_or_else(|| anyhow!("Unknown world {world}"))?;
+                world.load_level(&burrow, depth)
+            }
+            Source::File { path } => {
+                let data = std::fs::read_to_string(&path)
+                    .with_context(|| format!("Reading {}", path.display()))?;
+                let mut state = LevelState::new();
+                state
+                    .parse_level(&data)
+                    .with_context(|| format!("Parsing {}", path.display()))?;
+                Ok(state)
+            }
+            Source::State { state } => Ok(state),
+        }
+    }
+}
+
+/// Answer the JSON requests on the lines of `input` with a line of JSON each on `output`,
+/// until `input` ends.
+///
+/// Requests name a `command` and its arguments, e.g. `{"command": "move", "direction": "Up"}`.
+/// Failed requests are answered with `{"ok": false, "error": ...}` and change nothing.
+pub(crate) fn serve(
+    input: impl BufRead,
+    mut output: impl Write,
+    burrows: PathBuf,
+    search: Search,
+    check_collisions: bool,
+) -> anyhow::Result<()> {
+    let mut server = Server {
+        burrows,
+        search,
+        check_collisions,
+        worlds: None,
+        state: None,
+        undo_stack: Vec::new(),
+    };
+    for line in input.lines() {
+        let line = line.context("Reading request")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = serde_json::from_str(&line)
+            .context("Invalid request")
+            .and_then(|request| server.handle(request))
+            .unwrap_or_else(|e| Response {
+                error: Some(format!("{e:#}")),
+                ..Default::default()
+            });
+        serde_json::to_writer(&mut output, &response)?;
+        writeln!(output)?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use crate::{data::levels::level, solver::Search};
+
+    use super::serve;
+
+    #[test]
+    fn test_serve() {
+        let state = level(&["###############", "#P.b.."]);
+        let requests = [
+            json!({"command": "move", "direction": "Right"}),
+            json!({"command": "load", "state": state}),
+            json!({"command": "move", "direction": "Up"}),
+            json!({"command": "move", "direction": "Right"}),
+            json!({"command": "undo"}),
+            json!({"command": "solve"}),
+        ];
+        let input: String = requests.iter().map(|r| format!("{r}\n")).collect();
+        let mut output = Vec::new();
+        serve(
+            input.as_bytes(),
+            &mut output,
+            "burrows".into(),
+            Search::default(),
+            false,
+        )
+        .unwrap();
+
+        let responses: Vec<Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let ok: Vec<_> = responses.iter().map(|r| r["ok"].as_bool()).collect();
+        assert_eq!(
+            ok,
+            [false, true, false, true, true, true].map(Some).to_vec()
+        );
+        assert_eq!(responses[0]["error"], "No level loaded");
+        assert_eq!(responses[3]["frames"].as_array().unwrap().len(), 1);
+        assert_eq!(responses[4]["state"], responses[1]["state"]);
+        assert_eq!(
+            responses[5]["solution"],
+            json!(vec![json!({"Move": "Right"}); 4])
+        );
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let requests = [
+            json!({"command": "load"}),
+            json!({"command": "load", "world": "Test", "burrow": "A"}),
+            json!({"command": "load", "path": 1}),
+        ];
+        let input: String = requests.iter().map(|r| format!("{r}\n")).collect();
+        let mut output = Vec::new();
+        serve(
+            input.as_bytes(),
+            &mut output,
+            "burrows".into(),
+            Search::default(),
+            false,
+        )
+        .unwrap();
+
+        let errors: Vec<String> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str::<Value>(l).unwrap()["error"].to_string())
+            .collect();
+        assert!(errors[0].contains("Missing `world`, `path` or `state`"));
+        assert!(errors[1].contains("Invalid world to load: missing field `depth`"));
+        assert!(errors[2].contains("Invalid file to load: invalid type"));
+    }
+}
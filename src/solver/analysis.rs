@@ -0,0 +1,95 @@
+use std::collections::HashSet;
+
+use crate::data::{
+    grid::{Direction, GroundTile},
+    level_state::BunState,
+    LevelState, Position,
+};
+
+use strum::IntoEnumIterator;
+
+use super::search_state::Bitboard;
+
+/// Where Paquerette and the buns of a level can get to by moving, without using items
+#[derive(Debug, PartialEq, Eq)]
+pub struct Analysis {
+    /// tiles Paquerette can walk to
+    pub paquerette: Bitboard,
+    /// for each bun of the level, in order, the tiles it can be chased to;
+    /// empty for buns that were caught or escaped already
+    pub buns: Vec<Bitboard>,
+}
+
+pub fn analyze(state: &LevelState) -> Analysis {
+    let paquerette = Bitboard::flood(state.get_paquerette(), |p, d| {
+        state.tile_at(p + d) == Some(GroundTile::Floor { is_entry: false })
+    });
+    let buns = state
+        .buns()
+        .iter()
+        .map(|&bun| match bun {
+            BunState::In(p) => chase(state, p),
+            _ => Bitboard::default(),
+        })
+        .collect();
+    Analysis { paquerette, buns }
+}
+
+/// The tiles the bun at `bun` passes through while Paquerette chases it around,
+/// found by playing every sequence of moves with the other buns taken out
+fn chase(state: &LevelState, bun: Position) -> Bitboard {
+    let mut start = state.clone();
+    start.set_buns(vec![BunState::In(bun)]);
+    let mut res = Bitboard::default();
+    res.set(bun);
+    let mut visited = HashSet::from([(start.get_paquerette(), bun)]);
+    let mut todo = vec![start];
+    while let Some(level) = todo.pop() {
+        for d in Direction::iter() {
+            let mut next = level.clone();
+            let Ok(frames) = next.move_to(d) else {
+                continue;
+            };
+            for frame in &frames {
+                if let [BunState::In(p)] = frame.buns() {
+                    res.set(*p);
+                }
+            }
+            // caught or escaped buns are chased no further
+            let [BunState::In(p)] = *next.buns() else {
+                continue;
+            };
+            res.set(p);
+            if visited.insert((next.get_paquerette(), p)) {
+                todo.push(next);
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::{levels::level, Position};
+
+    use super::analyze;
+
+    #[test]
+    fn test_analyze() {
+        let state = level(&[
+            "###############",
+            "#P...b.#......#",
+            "#####.##......#",
+            "#####.##......#",
+        ]);
+        let analysis = analyze(&state);
+        let p = |x, y| Position::try_from((x, y)).unwrap();
+        assert!(analysis.paquerette.get(p(5, 3)));
+        assert!(!analysis.paquerette.get(p(8, 1)));
+        // the bun can only be chased into the dead end to its right
+        assert_eq!(
+            analysis.buns[0].iter().collect::<Vec<_>>(),
+            [p(5, 1), p(6, 1)]
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use crate::data::LevelState;
+
+use super::{
+    backtrack, deadlock,
+    heuristic::Heuristic,
+    search_state::{Layout, TooManyBuns},
+    visited::StateMap,
+    Node, Solution,
+};
+
+/// A* search over [`LevelState::apply`], guided by `heuristic`.
+///
+/// Every action costs one, so as long as the heuristic never overestimates,
+/// the solution has as few actions as the one of [`super::solve_bfs`].
+pub fn solve_astar(
+    start: &LevelState,
+    heuristic: &impl Heuristic,
+    check_collisions: bool,
+) -> Result<Option<Solution>, TooManyBuns> {
+    let layout = Layout::new(start)?;
+    let mut best = StateMap::new(check_collisions);
+    let mut nodes = vec![Node {
+        state: layout.compact(start),
+        parent: None,
+    }];
+    // ordered by estimated total cost, then by fewest actions taken
+    let mut open = BinaryHeap::new();
+    best.insert(&nodes[0].state, 0);
+    let Some(estimate) = heuristic.estimate(start) else {
+        return Ok(None);
+    };
+    open.push(Reverse((estimate, 0, 0)));
+
+    while let Some(Reverse((_, cost, index))) = open.pop() {
+        if best.get(&nodes[index].state).is_some_and(|&c| c < cost) {
+            // reached again more cheaply since it was queued
+            continue;
+        }
+        if nodes[index].state.is_solved() {
+            return Ok(Some(backtrack(&nodes, index)));
+        }
+        let level = layout.expand(&nodes[index].state);
+        for action in level.actions() {
+            let mut next_level = level.clone();
+            if next_level.apply_without_frames(action).is_err() || deadlock::is_dead(&next_level) {
+                continue;
+            }
+            let state = layout.compact(&next_level);
+            let next_cost = cost + 1;
+            if best.get(&state).is_some_and(|&c| c <= next_cost) {
+                continue;
+            }
+            let Some(estimate) = heuristic.estimate(&next_level) else {
+                continue;
+            };
+            best.insert(&state, next_cost);
+            open.push(Reverse((next_cost + estimate, next_cost, nodes.len())));
+            nodes.push(Node {
+                state,
+                parent: Some((index, action)),
+            });
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,110 @@
+use std::borrow::Cow;
+
+use crate::data::{grid::GroundTile, level_state::BunState, Item, LevelState, Position};
+
+use super::search_state::Bitboard;
+
+/// Why a level can't be solved anymore
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Deadlock {
+    /// the bun with this index left the level
+    Escaped(usize),
+    /// no tile the bun at this position can get to is reachable for Paquerette
+    OutOfReach(Position),
+}
+
+/// Find a reason why `state` can't be solved without leaving the level, if there is one.
+///
+/// Only certain deadlocks are found: all walls Paquerette could break with the pickaxes
+/// of the level are taken to be broken, and buns are assumed to go anywhere they can step to.
+pub fn find_deadlock(state: &LevelState) -> Option<Deadlock> {
+    if let Some(index) = find_escaped(state) {
+        return Some(Deadlock::Escaped(index));
+    }
+    let buns: Vec<Position> = state
+        .buns()
+        .iter()
+        .filter_map(|b| match b {
+            BunState::In(p) => Some(*p),
+            _ => None,
+        })
+        .collect();
+    if buns.is_empty() {
+        return None;
+    }
+
+    let has_pickaxe = state.inventory()[Item::Pickaxe] > 0
+        || state.items().any(|(_, item)| item == Item::Pickaxe);
+    let mut opened = Cow::Borrowed(state);
+    if has_pickaxe {
+        for p in Position::iter() {
+            if let Some(GroundTile::Wall {
+                breakable: true, ..
+            }) = state.tile_at(p)
+            {
+                opened
+                    .to_mut()
+                    .set_tile_at(p, GroundTile::Floor { is_entry: false });
+            }
+        }
+    }
+
+    let paquerette = Bitboard::flood(state.get_paquerette(), |p, d| {
+        let next = p + d;
+        opened.tile_at(next) == Some(GroundTile::Floor { is_entry: false })
+    });
+    buns.into_iter()
+        .find(|&bun| {
+            let reach = Bitboard::flood(bun, |p, d| {
+                let next = p + d;
+                next.is_inner()
+                    && opened.bun_can_step(p, d)
+                    && opened.tile_at(next) != Some(GroundTile::Hole)
+            });
+            let out_of_reach = reach.iter().all(|p| !paquerette.get(p));
+            out_of_reach
+        })
+        .map(Deadlock::OutOfReach)
+}
+
+/// Whether `state` can't be solved anymore, see [`find_deadlock`]
+pub fn is_dead(state: &LevelState) -> bool {
+    find_deadlock(state).is_some()
+}
+
+/// The index of a bun that left the level, if any did
+pub fn find_escaped(state: &LevelState) -> Option<usize> {
+    state
+        .buns()
+        .iter()
+        .position(|b| matches!(b, BunState::Escaped(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::{grid::Direction, levels::level, Position};
+
+    use super::{find_deadlock, Deadlock};
+
+    #[test]
+    fn test_out_of_reach() {
+        let mut state = level(&[
+            "###############",
+            "#P..#.b.......#",
+            "#...%.........#",
+            "#...#.........#",
+        ]);
+        let bun = Position::try_from((6, 1)).unwrap();
+        assert_eq!(find_deadlock(&state), Some(Deadlock::OutOfReach(bun)));
+        state.add_inventory([0, 1, 0, 0]);
+        assert_eq!(find_deadlock(&state), None);
+    }
+
+    #[test]
+    fn test_escaped() {
+        let mut state = level(&["###############", "#P.bo"]);
+        assert_eq!(find_deadlock(&state), None);
+        state.move_to(Direction::Right).unwrap();
+        assert_eq!(find_deadlock(&state), Some(Deadlock::Escaped(0)));
+    }
+}
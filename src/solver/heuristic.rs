@@ -0,0 +1,147 @@
+use std::collections::VecDeque;
+
+use strum::IntoEnumIterator;
+
+use crate::data::{
+    grid::{Direction, GroundTile, TileItem, LEVEL_HEIGHT, LEVEL_WIDTH},
+    level_state::BunState,
+    Item, LevelState, Position,
+};
+
+/// Estimates the number of actions left to solve a level, to guide [`super::solve_astar`].
+///
+/// To keep the found solutions optimal, estimates must never exceed the real number of actions.
+pub trait Heuristic {
+    /// A lower bound on the actions needed to solve `state`, `None` if it is unsolvable
+    fn estimate(&self, state: &LevelState) -> Option<usize>;
+}
+
+/// Whether Paquerette can catch a bun with the same move that scares it
+fn can_trap(state: &LevelState) -> bool {
+    state.inventory()[Item::Trap] > 0
+        || state
+            .content()
+            .any(|(_, (_, item))| item == Some(TileItem::Placed(Item::Trap)))
+}
+
+/// Actions needed to catch a bun once Paquerette is close enough to scare it
+fn catch_cost(state: &LevelState) -> usize {
+    if can_trap(state) {
+        0
+    } else {
+        1
+    }
+}
+
+fn uncaught_buns(state: &LevelState) -> impl Iterator<Item = Position> + '_ {
+    state.buns().iter().filter_map(|b| match b {
+        BunState::In(p) => Some(*p),
+        _ => None,
+    })
+}
+
+/// A bun only moves when Paquerette is in a straight line at most two tiles away,
+/// so every bun out of that range needs a move to get there before it can be caught.
+pub struct StraightLine;
+
+impl Heuristic for StraightLine {
+    fn estimate(&self, state: &LevelState) -> Option<usize> {
+        if state
+            .buns()
+            .iter()
+            .any(|b| matches!(b, BunState::Escaped(_)))
+        {
+            return None;
+        }
+        let catch = catch_cost(state);
+        let p = state.get_paquerette();
+        Some(
+            uncaught_buns(state)
+                .map(|bun| match p.distance_to_straight_line(bun) {
+                    Some((_, dist)) if dist <= 2 => 1,
+                    _ => 1 + catch,
+                })
+                .max()
+                .unwrap_or(0),
+        )
+    }
+}
+
+/// Shortest walks of Paquerette to every tile of the current level to a position
+/// where she scares a bun. Breakable walls count as walkable, since a pickaxe might turn up.
+pub struct GridDistance;
+
+impl GridDistance {
+    fn walkable(state: &LevelState, p: Position) -> bool {
+        match state.get_at(p) {
+            Some((GroundTile::Wall { breakable, .. }, _)) => breakable,
+            Some((GroundTile::Floor { is_entry }, _)) => !is_entry,
+            Some((GroundTile::Hole, _)) | None => false,
+        }
+    }
+
+    /// Number of moves for Paquerette to reach each tile
+    fn distances(
+        state: &LevelState,
+    ) -> [[Option<usize>; LEVEL_HEIGHT as usize]; LEVEL_WIDTH as usize] {
+        let mut res = [[None; LEVEL_HEIGHT as usize]; LEVEL_WIDTH as usize];
+        let start = state.get_paquerette();
+        let mut queue = VecDeque::from([(start, 0)]);
+        if let Some((x, y)) = state.index_for(start) {
+            res[x][y] = Some(0);
+        }
+        while let Some((p, dist)) = queue.pop_front() {
+            for d in Direction::iter() {
+                let next = p + d;
+                let Some((x, y)) = state.index_for(next) else {
+                    continue;
+                };
+                if res[x][y].is_none() && Self::walkable(state, next) {
+                    res[x][y] = Some(dist + 1);
+                    queue.push_back((next, dist + 1));
+                }
+            }
+        }
+        res
+    }
+}
+
+impl Heuristic for GridDistance {
+    fn estimate(&self, state: &LevelState) -> Option<usize> {
+        if state
+            .buns()
+            .iter()
+            .any(|b| matches!(b, BunState::Escaped(_)))
+        {
+            return None;
+        }
+        let distances = Self::distances(state);
+        let catch = catch_cost(state);
+        let mut res = 0;
+        for bun in uncaught_buns(state) {
+            // same conditions as in `LevelState::move_to` for a bun to flee or be caught,
+            // a bun in a tunnel of a breakable wall waits for the pickaxe
+            if state.is_solid(bun) && !Self::walkable(state, bun) {
+                return None;
+            }
+            let mut closest = None;
+            for d in Direction::iter() {
+                let mut p = bun;
+                for _ in 0..2 {
+                    p += d;
+                    let Some((x, y)) = state.index_for(p) else {
+                        break;
+                    };
+                    if let Some(dist) = distances[x][y] {
+                        closest = Some(closest.map_or(dist, |c: usize| c.min(dist)));
+                    }
+                    if state.is_solid(p) {
+                        break;
+                    }
+                }
+            }
+            res = res.max(1.max(closest? + catch));
+        }
+        Some(res)
+    }
+}
@@ -0,0 +1,248 @@
+pub mod analysis;
+pub mod astar;
+pub mod deadlock;
+pub mod heuristic;
+pub mod parallel;
+pub mod planner;
+pub mod search_state;
+pub mod visited;
+
+use std::str::FromStr;
+
+use crate::data::{level_state::Action, LevelState};
+
+use self::{
+    search_state::{Layout, SearchState, TooManyBuns},
+    visited::StateMap,
+};
+
+pub use self::{astar::solve_astar, parallel::solve_parallel};
+
+/// A sequence of actions for Paquerette, starting from the searched level state
+pub type Solution = Vec<Action>;
+
+/// How to search for a solution
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Search {
+    Bfs,
+    /// [`solve_parallel`] on every core
+    ParallelBfs,
+    /// A* with [`heuristic::StraightLine`]
+    StraightLine,
+    /// A* with [`heuristic::GridDistance`]
+    #[default]
+    GridDistance,
+}
+
+impl FromStr for Search {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bfs" => Ok(Search::Bfs),
+            "parallel" => Ok(Search::ParallelBfs),
+            "line" => Ok(Search::StraightLine),
+            "grid" => Ok(Search::GridDistance),
+            _ => Err(format!(
+                "Unknown search {s}, expected bfs, parallel, line or grid"
+            )),
+        }
+    }
+}
+
+/// Solve with `search`; with `check_collisions`, states are compared in full
+/// instead of trusting their Zobrist keys, see [`StateMap`]
+pub fn solve(
+    start: &LevelState,
+    search: Search,
+    check_collisions: bool,
+) -> Result<Option<Solution>, TooManyBuns> {
+    match search {
+        Search::Bfs => solve_bfs(start, check_collisions),
+        Search::ParallelBfs => {
+            let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+            solve_parallel(start, threads, check_collisions)
+        }
+        Search::StraightLine => solve_astar(start, &heuristic::StraightLine, check_collisions),
+        Search::GridDistance => solve_astar(start, &heuristic::GridDistance, check_collisions),
+    }
+}
+
+struct Node {
+    state: SearchState,
+    parent: Option<(usize, Action)>,
+}
+
+/// Breadth-first search over [`LevelState::apply`].
+///
+/// Returns the shortest sequence of actions that catches every bun,
+/// or `None` if the level is unsolvable from `start`.
+pub fn solve_bfs(
+    start: &LevelState,
+    check_collisions: bool,
+) -> Result<Option<Solution>, TooManyBuns> {
+    let layout = Layout::new(start)?;
+    let start = layout.compact(start);
+    let mut visited = StateMap::new(check_collisions);
+    visited.insert(&start, ());
+    // nodes are expanded in insertion order, so the vector doubles as the queue
+    let mut nodes = vec![Node {
+        state: start,
+        parent: None,
+    }];
+
+    let mut next = 0;
+    while next < nodes.len() {
+        if nodes[next].state.is_solved() {
+            return Ok(Some(backtrack(&nodes, next)));
+        }
+        let level = layout.expand(&nodes[next].state);
+        for action in level.actions() {
+            let mut state = level.clone();
+            if state.apply_without_frames(action).is_err() || deadlock::is_dead(&state) {
+                continue;
+            }
+            let state = layout.compact(&state);
+            if visited.insert(&state, ()) {
+                nodes.push(Node {
+                    state,
+                    parent: Some((next, action)),
+                });
+            }
+        }
+        next += 1;
+    }
+    Ok(None)
+}
+
+fn backtrack(nodes: &[Node], mut index: usize) -> Solution {
+    let mut res = Vec::new();
+    while let Some((parent, action)) = nodes[index].parent {
+        res.push(action);
+        index = parent;
+    }
+    res.reverse();
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::{
+        grid::{Direction, GroundTile},
+        level_state::Action,
+        levels::{level, walled},
+        Item, Position,
+    };
+
+    use super::{
+        heuristic::{GridDistance, Heuristic, StraightLine},
+        search_state::{TooManyBuns, MAX_BUNS},
+        solve, solve_astar, solve_bfs, solve_parallel, Search,
+    };
+
+    #[test]
+    fn test_bfs_corridor() {
+        let state = level(&["###############", "#P...b"]);
+        assert_eq!(
+            solve_bfs(&state, true).unwrap(),
+            Some(vec![Action::Move(Direction::Right); 4])
+        );
+    }
+
+    #[test]
+    fn test_bfs_unsolvable() {
+        let state = level(&["###############", "#P..#b"]);
+        assert_eq!(solve_bfs(&state, true).unwrap(), None);
+    }
+
+    #[test]
+    fn test_bfs_pickaxe() {
+        let mut state = level(&["###############", "#P%.b"]);
+        assert_eq!(solve_bfs(&state, true).unwrap(), None);
+        state.add_inventory([0, 1, 0, 0]);
+        let mut expected = vec![Action::Use(Item::Pickaxe, Direction::Right)];
+        expected.extend([Action::Move(Direction::Right); 3]);
+        assert_eq!(solve_bfs(&state, true).unwrap(), Some(expected));
+    }
+
+    #[test]
+    fn test_too_many_buns() {
+        let mut state = walled();
+        let floor = Position::iter()
+            .filter(|&p| state.tile_at(p) == Some(GroundTile::Floor { is_entry: false }));
+        for p in floor.take(MAX_BUNS + 1).collect::<Vec<_>>() {
+            state.set_bunny(p);
+        }
+        assert_eq!(
+            solve(&state, Search::Bfs, false),
+            Err(TooManyBuns(MAX_BUNS + 1))
+        );
+    }
+
+    #[test]
+    fn test_astar_bun_in_breakable_tunnel() {
+        let mut state = level(&["###############", "#P.b%"]);
+        let tunnel = Position::try_from((4, 1)).unwrap();
+        state.set_tile_at(
+            tunnel,
+            GroundTile::Wall {
+                breakable: true,
+                tunnels: [false, true, false, false],
+            },
+        );
+        state.add_inventory([0, 1, 0, 0]);
+        let bfs = solve_bfs(&state, true).unwrap().expect("solvable");
+        assert_eq!(
+            bfs,
+            [
+                Action::Move(Direction::Right),
+                Action::Move(Direction::Right),
+                Action::Use(Item::Pickaxe, Direction::Right),
+                Action::Move(Direction::Right),
+            ]
+        );
+        assert_eq!(solve_astar(&state, &GridDistance, true).unwrap(), Some(bfs));
+    }
+
+    #[test]
+    fn test_astar_matches_bfs() {
+        let mut state = level(&[
+            "###############",
+            "#P....#.......#",
+            "#.###.#.#####.#",
+            "#.#b..........#",
+            "#.#####.###.#.#",
+            "#.......#b..#.#",
+            "#.###.#.#####.#",
+            "#...%.#.......#",
+        ]);
+        state.add_inventory([1, 0, 0, 0]);
+        let bfs = solve_bfs(&state, true).unwrap().expect("solvable");
+        assert_eq!(
+            solve_astar(&state, &StraightLine, false)
+                .unwrap()
+                .map(|s| s.len()),
+            Some(bfs.len())
+        );
+        assert_eq!(
+            solve_astar(&state, &GridDistance, false)
+                .unwrap()
+                .map(|s| s.len()),
+            Some(bfs.len())
+        );
+        let parallel = solve_parallel(&state, 4, false).unwrap().expect("solvable");
+        assert_eq!(parallel.len(), bfs.len());
+        let mut solved = state.clone();
+        for &action in &parallel {
+            solved.apply(action).unwrap();
+        }
+        assert!(solved.is_solved());
+
+        // the estimates along an optimal solution never exceed the actions left
+        for (done, &action) in bfs.iter().enumerate() {
+            assert!(StraightLine.estimate(&state).unwrap() <= bfs.len() - done);
+            assert!(GridDistance.estimate(&state).unwrap() <= bfs.len() - done);
+            state.apply(action).unwrap();
+        }
+    }
+}
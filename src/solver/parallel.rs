@@ -0,0 +1,99 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use crate::data::LevelState;
+
+use super::{
+    deadlock,
+    search_state::{Layout, TooManyBuns},
+    visited::SharedStateSet,
+    Node, Solution,
+};
+
+/// Nodes a thread claims at once when expanding a layer
+const CHUNK: usize = 64;
+
+/// Breadth-first search over [`LevelState::apply`] on `threads` threads.
+///
+/// The search goes layer by layer: the threads claim chunks of the current layer
+/// until none are left, so a thread done early takes over work the others haven't started.
+/// New states are checked against a visited set shared by all threads.
+/// The solution may differ from the one of [`super::solve_bfs`], but is just as short.
+pub fn solve_parallel(
+    start: &LevelState,
+    threads: usize,
+    check_collisions: bool,
+) -> Result<Option<Solution>, TooManyBuns> {
+    let layout = Layout::new(start)?;
+    let start = layout.compact(start);
+    let visited = SharedStateSet::new(check_collisions);
+    visited.insert(&start);
+    // the parents of the nodes of a layer are indices into the layer before
+    let mut layers = vec![vec![Node {
+        state: start,
+        parent: None,
+    }]];
+
+    loop {
+        let layer = layers.last().expect("at least the start");
+        if layer.is_empty() {
+            return Ok(None);
+        }
+        if let Some(index) = layer.iter().position(|n| n.state.is_solved()) {
+            return Ok(Some(backtrack_layers(&layers, index)));
+        }
+
+        let next_chunk = AtomicUsize::new(0);
+        let expand = || {
+            let mut res = Vec::new();
+            loop {
+                let begin = next_chunk.fetch_add(CHUNK, Ordering::Relaxed);
+                if begin >= layer.len() {
+                    return res;
+                }
+                let end = layer.len().min(begin + CHUNK);
+                for (index, node) in layer[begin..end].iter().enumerate() {
+                    let index = begin + index;
+                    let level = layout.expand(&node.state);
+                    for action in level.actions() {
+                        let mut state = level.clone();
+                        if state.apply_without_frames(action).is_err() || deadlock::is_dead(&state)
+                        {
+                            continue;
+                        }
+                        let state = layout.compact(&state);
+                        if visited.insert(&state) {
+                            res.push(Node {
+                                state,
+                                parent: Some((index, action)),
+                            });
+                        }
+                    }
+                }
+            }
+        };
+        let next = thread::scope(|s| {
+            let workers: Vec<_> = (0..threads.max(1)).map(|_| s.spawn(expand)).collect();
+            workers
+                .into_iter()
+                .flat_map(|w| w.join().expect("search thread panicked"))
+                .collect()
+        });
+        layers.push(next);
+    }
+}
+
+fn backtrack_layers(layers: &[Vec<Node>], mut index: usize) -> Solution {
+    let mut res = Vec::new();
+    for layer in layers.iter().rev() {
+        let Some((parent, action)) = layer[index].parent else {
+            break;
+        };
+        res.push(action);
+        index = parent;
+    }
+    res.reverse();
+    res
+}
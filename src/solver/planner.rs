@@ -0,0 +1,206 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+use anyhow::Context;
+
+use crate::data::{
+    level_state::Action,
+    world::{LevelId, World, WorldState},
+    Direction,
+};
+
+/// States a [`plan_world`] search may visit before giving up on proofs
+pub const DEFAULT_MAX_STATES: usize = 50_000;
+
+/// A part of a [`WorldPlan`]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Step {
+    /// Actions Paquerette takes inside `level`
+    Level {
+        level: LevelId,
+        actions: Vec<Action>,
+    },
+    /// Paquerette leaves `from` by moving in direction `d` and arrives in `to`
+    Transition {
+        from: LevelId,
+        to: LevelId,
+        d: Direction,
+    },
+}
+
+#[derive(Debug)]
+pub struct WorldPlan {
+    /// how to catch as many buns as the search found possible, from entering the world
+    pub steps: Vec<Step>,
+    /// buns caught by following the steps
+    pub caught: usize,
+    /// buns in all levels of the world
+    pub total: usize,
+    /// how many buns no plan can catch, if every reachable state was searched
+    pub uncatchable: Option<usize>,
+    /// levels with buns Paquerette can never enter, if every reachable state was searched
+    pub unreachable: Vec<LevelId>,
+}
+
+struct Node<'a> {
+    state: WorldState<'a>,
+    parent: Option<(usize, Action)>,
+}
+
+/// Plan a way through `world` that catches every bun.
+///
+/// The search goes over whole [`WorldState`]s, so it decides the order of the levels,
+/// where to use tools and which buns to chase into other levels along the way.
+/// It expands the states with the fewest uncaught buns first, which finds plans quickly
+/// but not necessarily the shortest ones. After visiting `max_states` states it settles
+/// for the plan catching the most buns so far and proves nothing about the others.
+pub fn plan_world(world: &World, max_states: usize) -> anyhow::Result<WorldPlan> {
+    let start = world.enter()?;
+    let levels = world.level_ids();
+    let total = levels.iter().map(|&id| world.bun_count(id)).sum();
+    let mut entered = HashSet::from([start.level()]);
+    // node indices by Zobrist key, colliding states are told apart in full
+    let mut visited = HashMap::from([(start.zobrist(), vec![0])]);
+    let mut open = BinaryHeap::from([Reverse((total - start.caught_buns(), 0, 0))]);
+    let mut nodes = vec![Node {
+        state: start,
+        parent: None,
+    }];
+    // the node with the most buns caught, then with the fewest actions
+    let mut best = 0;
+    let mut exhausted = true;
+
+    while let Some(Reverse((uncaught, actions, index))) = open.pop() {
+        if uncaught == 0 {
+            break;
+        }
+        if nodes.len() >= max_states {
+            exhausted = false;
+            break;
+        }
+        let state = nodes[index].state.clone();
+        for action in state.level_state.actions() {
+            let mut next = state.clone();
+            if next.apply(action).is_err() {
+                continue;
+            }
+            let same_key = visited.entry(next.zobrist()).or_default();
+            if same_key.iter().any(|&i| nodes[i].state == next) {
+                continue;
+            }
+            same_key.push(nodes.len());
+            entered.insert(next.level());
+            let caught = next.caught_buns();
+            if caught > nodes[best].state.caught_buns() {
+                best = nodes.len();
+            }
+            open.push(Reverse((total - caught, actions + 1, nodes.len())));
+            nodes.push(Node {
+                state: next,
+                parent: Some((index, action)),
+            });
+        }
+    }
+
+    let caught = nodes[best].state.caught_buns();
+    let (uncatchable, unreachable) = if caught == total {
+        (Some(0), Vec::new())
+    } else if exhausted {
+        let unreachable = levels
+            .into_iter()
+            .filter(|id| !entered.contains(id) && world.bun_count(*id) > 0)
+            .collect();
+        (Some(total - caught), unreachable)
+    } else {
+        (None, Vec::new())
+    };
+    Ok(WorldPlan {
+        steps: steps(world, &backtrack(&nodes, best))?,
+        caught,
+        total,
+        uncatchable,
+        unreachable,
+    })
+}
+
+fn backtrack(nodes: &[Node], mut index: usize) -> Vec<Action> {
+    let mut res = Vec::new();
+    while let Some((parent, action)) = nodes[index].parent {
+        res.push(action);
+        index = parent;
+    }
+    res.reverse();
+    res
+}
+
+/// Split the actions from entering `world` into steps by the levels they are taken in
+fn steps(world: &World, actions: &[Action]) -> anyhow::Result<Vec<Step>> {
+    let mut state = world.enter()?;
+    let mut res = Vec::new();
+    let mut current = Vec::new();
+    for &action in actions {
+        let from = state.level();
+        state
+            .apply(action)
+            .with_context(|| format!("Planned action {action:?} failed"))?;
+        match action {
+            Action::Move(d) if state.level() != from => {
+                if !current.is_empty() {
+                    res.push(Step::Level {
+                        level: from,
+                        actions: std::mem::take(&mut current),
+                    });
+                }
+                res.push(Step::Transition {
+                    from,
+                    to: state.level(),
+                    d,
+                });
+            }
+            _ => current.push(action),
+        }
+    }
+    if !current.is_empty() {
+        res.push(Step::Level {
+            level: state.level(),
+            actions: current,
+        });
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::{world::World, Direction};
+
+    use super::{plan_world, Step, DEFAULT_MAX_STATES};
+
+    #[test]
+    fn test_chase_bun_down_hole() {
+        let world = World::bun_by_hole();
+        let plan = plan_world(&world, DEFAULT_MAX_STATES).unwrap();
+        assert_eq!((plan.caught, plan.total), (1, 1));
+        assert_eq!(plan.uncatchable, Some(0));
+        // Paquerette follows the bun down the hole and lands on it
+        let (top, below) = (world.level_id("A", 1), world.level_id("A", 2));
+        assert_eq!(
+            plan.steps.last(),
+            Some(&Step::Transition {
+                from: top.unwrap(),
+                to: below.unwrap(),
+                d: Direction::Right
+            })
+        );
+    }
+
+    #[test]
+    fn test_uncatchable() {
+        let world = World::two_levels("#P.b#b", "#P.b");
+        let plan = plan_world(&world, DEFAULT_MAX_STATES).unwrap();
+        assert_eq!((plan.caught, plan.total), (1, 3));
+        assert_eq!(plan.uncatchable, Some(2));
+        assert_eq!(plan.unreachable, [world.level_id("A", 2).unwrap()]);
+    }
+}
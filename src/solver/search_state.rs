@@ -0,0 +1,302 @@
+use std::fmt;
+
+use strum::{EnumCount, IntoEnumIterator};
+
+use crate::data::{
+    grid::{Direction, GroundTile, LEVEL_HEIGHT, LEVEL_WIDTH},
+    level_state::{BunState, LevelExit},
+    Item, LevelState, Position,
+};
+
+const TILE_COUNT: usize = LEVEL_WIDTH as usize * LEVEL_HEIGHT as usize;
+
+/// Most buns a level may have to be searched
+pub const MAX_BUNS: usize = 32;
+
+/// A level with more than [`MAX_BUNS`] buns, holding their number
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct TooManyBuns(pub usize);
+
+impl fmt::Display for TooManyBuns {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "too many buns to search: {}, at most {MAX_BUNS} are supported",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for TooManyBuns {}
+
+/// One bit per tile of a level, indexed by [`Position::to_index`]
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Default, Clone, Copy)]
+pub struct Bitboard([u64; TILE_COUNT.div_ceil(64)]);
+
+impl Bitboard {
+    pub fn get(&self, p: Position) -> bool {
+        let i = p.to_index();
+        self.0[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    pub fn set(&mut self, p: Position) {
+        let i = p.to_index();
+        self.0[i / 64] |= 1 << (i % 64);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Position> + '_ {
+        self.0.iter().enumerate().flat_map(|(i, &word)| {
+            // take the lowest set bit until none is left
+            let mut word = word;
+            std::iter::from_fn(move || {
+                let bit = word.trailing_zeros() as usize;
+                (word != 0).then(|| {
+                    word &= word - 1;
+                    Position::from_index(i * 64 + bit)
+                })
+            })
+        })
+    }
+
+    /// The tiles reachable from `start` by steps allowed by `can_step`
+    pub fn flood(start: Position, can_step: impl Fn(Position, Direction) -> bool) -> Self {
+        let mut res = Bitboard::default();
+        res.set(start);
+        let mut todo = vec![start];
+        while let Some(p) = todo.pop() {
+            for d in Direction::iter() {
+                let next = p + d;
+                if can_step(p, d) && !res.get(next) {
+                    res.set(next);
+                    todo.push(next);
+                }
+            }
+        }
+        res
+    }
+}
+
+const EMPTY_SLOT: u16 = u16::MAX;
+
+/// A bun state packed as its kind in the high byte and its tile index in the low byte
+fn pack_bun(bun: BunState) -> u16 {
+    let (kind, p) = match bun {
+        BunState::In(p) => (0, p),
+        BunState::Caught => return 1 << 8,
+        BunState::Escaped(LevelExit::Down(p)) => (2, p),
+        BunState::Escaped(LevelExit::Up(p)) => (3, p),
+        BunState::Escaped(LevelExit::Side(p, d)) => (4 + d as u16, p),
+    };
+    kind << 8 | p.to_index() as u16
+}
+
+fn unpack_bun(packed: u16) -> BunState {
+    let p = Position::from_index((packed & 0xff) as usize);
+    match packed >> 8 {
+        0 => BunState::In(p),
+        1 => BunState::Caught,
+        2 => BunState::Escaped(LevelExit::Down(p)),
+        3 => BunState::Escaped(LevelExit::Up(p)),
+        d => BunState::Escaped(LevelExit::Side(
+            p,
+            Direction::from_repr(d as usize - 4).expect("packed direction"),
+        )),
+    }
+}
+
+/// The changing parts of a [`LevelState`], relative to its [`Layout`].
+///
+/// Buns are kept sorted, so states only differing in the order of their buns are the same.
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct SearchState {
+    paquerette: Position,
+    buns: [u16; MAX_BUNS],
+    /// breakable walls that were broken
+    broken: Bitboard,
+    /// floor that was dug into holes
+    dug: Bitboard,
+    /// items of the layout that were picked up
+    taken: Bitboard,
+    traps: Bitboard,
+    carrots: Bitboard,
+    /// one byte per item count
+    inventory: u32,
+    /// [`LevelState::zobrist`] of the expanded state
+    key: u64,
+}
+
+impl SearchState {
+    pub fn key(&self) -> u64 {
+        self.key
+    }
+
+    pub fn is_solved(&self) -> bool {
+        self.buns
+            .iter()
+            .all(|&b| b == EMPTY_SLOT || unpack_bun(b) == BunState::Caught)
+    }
+}
+
+/// The parts of a level that stay the same during a search, shared by all its [`SearchState`]s
+pub struct Layout {
+    base: LevelState,
+    /// the tiles whose ground can change: breakable walls and floor that can be dug
+    changeable: Bitboard,
+}
+
+impl Layout {
+    /// The layout of the level `start` is in; buns, placed items and inventory don't belong to it
+    pub fn new(start: &LevelState) -> Result<Self, TooManyBuns> {
+        if start.buns().len() > MAX_BUNS {
+            return Err(TooManyBuns(start.buns().len()));
+        }
+        let mut base = start.clone();
+        base.set_buns(Vec::new());
+        base.set_inventory(Default::default());
+        base.clear_placed();
+        let mut changeable = Bitboard::default();
+        for p in Position::iter() {
+            if let Some(
+                GroundTile::Wall {
+                    breakable: true, ..
+                }
+                | GroundTile::Floor { is_entry: false },
+            ) = base.tile_at(p)
+            {
+                changeable.set(p);
+            }
+        }
+        Ok(Layout { base, changeable })
+    }
+
+    pub fn compact(&self, level: &LevelState) -> SearchState {
+        let mut buns = [EMPTY_SLOT; MAX_BUNS];
+        for (slot, &bun) in buns.iter_mut().zip(level.buns()) {
+            *slot = pack_bun(bun);
+        }
+        buns.sort_unstable();
+
+        let mut res = SearchState {
+            paquerette: level.get_paquerette(),
+            buns,
+            broken: Bitboard::default(),
+            dug: Bitboard::default(),
+            taken: Bitboard::default(),
+            traps: Bitboard::default(),
+            carrots: Bitboard::default(),
+            inventory: 0,
+            key: level.zobrist(),
+        };
+        for p in self.changeable.iter() {
+            let tile = level.tile_at(p).expect("inner");
+            if tile != self.base.tile_at(p).expect("inner") {
+                match tile {
+                    GroundTile::Floor { .. } => res.broken.set(p),
+                    GroundTile::Hole => res.dug.set(p),
+                    GroundTile::Wall { .. } => unreachable!("walls are never built"),
+                }
+            }
+        }
+        for (p, _) in self.base.items() {
+            if level.get_item_at(p).is_none() {
+                res.taken.set(p);
+            }
+        }
+        for (p, item) in level.placed() {
+            match item {
+                Item::Trap => res.traps.set(p),
+                Item::Carrot => res.carrots.set(p),
+                _ => (),
+            }
+        }
+        let inventory = level.inventory();
+        for item in Item::iter() {
+            res.inventory |= (inventory[item] as u32) << (8 * item as usize);
+        }
+        res
+    }
+
+    pub fn expand(&self, state: &SearchState) -> LevelState {
+        let mut res = self.base.clone();
+        // the key of the state is known, so the changes don't need to update it
+        let mut changes = res.unkeyed(state.key);
+        changes.set_paquerette(state.paquerette);
+        changes.set_buns(
+            state
+                .buns
+                .iter()
+                .take_while(|&&b| b != EMPTY_SLOT)
+                .map(|&b| unpack_bun(b)),
+        );
+        for p in state.broken.iter() {
+            changes.set_tile_at(p, GroundTile::Floor { is_entry: false });
+        }
+        for p in state.dug.iter() {
+            changes.set_tile_at(p, GroundTile::Hole);
+        }
+        for p in state.taken.iter() {
+            changes.remove_item_at(p);
+        }
+        for p in state.traps.iter() {
+            changes.set_placed_at(p, Item::Trap);
+        }
+        for p in state.carrots.iter() {
+            changes.set_placed_at(p, Item::Carrot);
+        }
+        let mut inventory = [0; Item::COUNT];
+        for item in Item::iter() {
+            inventory[item] = (state.inventory >> (8 * item as usize)) as u8;
+        }
+        changes.set_inventory(inventory);
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::{
+        grid::Direction, level_state::Action, levels::level, Item, LevelState, Position,
+    };
+
+    use super::Layout;
+
+    #[test]
+    fn test_round_trip() {
+        let mut state = level(&[
+            "###############",
+            "#P%.b.........#",
+            "#.............#",
+            "#..b.......o..#",
+            "#.............#",
+            "#.............#",
+            "#.............#",
+            "#.............#",
+        ]);
+        state.add_inventory([1, 1, 1, 1]);
+        let item = Position::try_from((8, 5)).unwrap();
+        state.insert_item(item, Item::Pickaxe);
+        let layout = Layout::new(&state).unwrap();
+        let round_trip = |state: &LevelState| {
+            let compact = layout.compact(state);
+            let expanded = layout.expand(&compact);
+            assert_eq!(layout.compact(&expanded), compact);
+            // the order of buns is not kept, but doesn't matter
+            assert_eq!(&expanded, state);
+        };
+        for action in [
+            Action::Use(Item::Pickaxe, Direction::Right),
+            Action::Use(Item::Shovel, Direction::Down),
+            Action::Move(Direction::Right),
+            Action::Use(Item::Trap, Direction::Down),
+            Action::Use(Item::Carrot, Direction::Right),
+        ] {
+            let mut searched = state.clone();
+            state.apply(action).unwrap();
+            searched.apply_without_frames(action).unwrap();
+            assert_eq!(searched, state);
+            round_trip(&state);
+        }
+        state.remove_item_at(item);
+        round_trip(&state);
+    }
+}
@@ -0,0 +1,86 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::Mutex,
+};
+
+use super::search_state::SearchState;
+
+/// Values per search state, keyed by the state's Zobrist key.
+///
+/// Without collision checks two states with the same key are taken to be the same,
+/// which saves keeping the states around; with them, the states are compared in full.
+pub struct StateMap<V> {
+    entries: HashMap<u64, Vec<(Option<SearchState>, V)>>,
+    check_collisions: bool,
+}
+
+impl<V> StateMap<V> {
+    pub fn new(check_collisions: bool) -> Self {
+        StateMap {
+            entries: HashMap::new(),
+            check_collisions,
+        }
+    }
+
+    pub fn get(&self, state: &SearchState) -> Option<&V> {
+        self.entries
+            .get(&state.key())?
+            .iter()
+            .find(|(s, _)| s.as_ref().is_none_or(|s| s == state))
+            .map(|(_, v)| v)
+    }
+
+    /// Set the value of `state`, returns whether it is new
+    pub fn insert(&mut self, state: &SearchState, value: V) -> bool {
+        let stored = self.check_collisions.then(|| state.clone());
+        match self.entries.entry(state.key()) {
+            Entry::Vacant(e) => {
+                e.insert(vec![(stored, value)]);
+                true
+            }
+            Entry::Occupied(mut e) => {
+                let same = e
+                    .get_mut()
+                    .iter_mut()
+                    .find(|(s, _)| s.as_ref().is_none_or(|s| s == state));
+                match same {
+                    Some((_, v)) => {
+                        *v = value;
+                        false
+                    }
+                    None => {
+                        e.get_mut().push((stored, value));
+                        true
+                    }
+                }
+            }
+        }
+    }
+}
+
+const SHARDS: usize = 64;
+
+/// A set of search states shared between threads, split into shards by key
+/// so that threads rarely wait for each other
+pub struct SharedStateSet {
+    shards: Vec<Mutex<StateMap<()>>>,
+}
+
+impl SharedStateSet {
+    pub fn new(check_collisions: bool) -> Self {
+        SharedStateSet {
+            shards: (0..SHARDS)
+                .map(|_| Mutex::new(StateMap::new(check_collisions)))
+                .collect(),
+        }
+    }
+
+    /// Add `state`, returns whether it is new
+    pub fn insert(&self, state: &SearchState) -> bool {
+        let shard = &self.shards[state.key() as usize % SHARDS];
+        shard
+            .lock()
+            .expect("no panics while locked")
+            .insert(state, ())
+    }
+}
@@ -1,18 +1,29 @@
-use crate::data::{
-    grid::{Direction, GroundTile, TileItem},
-    level_state::TileContent,
-    LevelState, world::WorldState,
+mod editor;
+
+pub(crate) use editor::run_editor;
+
+use crate::{
+    data::{
+        grid::{tunnel_glyph, Direction, GroundTile, TileItem},
+        level_state::{Action, TileContent},
+        world::WorldState,
+        Item, LevelState, Position,
+    },
+    solver::{analysis::Analysis, deadlock},
 };
 
+use strum::IntoEnumIterator;
+
 use std::{
     io::{self, stdout, Write},
+    path::Path,
     time::{Duration, Instant}, slice::{Iter, IterMut}, vec::IntoIter,
 };
 
 use crossterm::{
     cursor::{self, MoveLeft, MoveTo, MoveToNextLine},
     event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
-    style::{Color, Print, SetBackgroundColor, SetForegroundColor},
+    style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor},
     terminal::{disable_raw_mode, enable_raw_mode, size, Clear, ClearType, SetSize},
     ExecutableCommand, QueueableCommand,
 };
@@ -20,7 +31,10 @@ use crossterm::{
 //const DRAW_HEIGHT: u16 = crate::data::grid::LEVEL_HEIGHT as u16 + 3;
 //const DRAW_WIDTH: u16 = LEVEL_WIDTH as u16 + 2;
 
-pub(crate) fn run_level(state: &mut LevelState) -> io::Result<()> {
+/// Play a world with the arrow keys or `wasd`, select an item to use with `1`-`4`,
+/// undo with `z`/`u`, redo with `y`/`r`, restart the level with `R` and quit with `ctrl+c`.
+/// Save to `save_path` with `ctrl+s` and load the saved game with `ctrl+o`.
+pub(crate) fn run_world(state: &mut WorldState, save_path: &Path) -> io::Result<()> {
     let (cols, rows) = size()?;
     enable_raw_mode()?;
     //thread::sleep(Duration::from_secs(2));
@@ -42,6 +56,12 @@ pub(crate) fn run_level(state: &mut LevelState) -> io::Result<()> {
     let mut display_queue: std::iter::Peekable<IntoIter<LevelState>> = Vec::new().into_iter().peekable();
     let duration_step = Duration::from_millis(100);
     let mut next_animation_step = Instant::now();
+    // item to use with the next direction key
+    let mut selected: Option<Item> = None;
+    let mut undo_stack = Vec::new();
+    let mut redo_stack = Vec::new();
+    // result of the last save or load
+    let mut message = String::new();
 
     while !abort {
         let mut applied_input = false;
@@ -63,15 +83,33 @@ pub(crate) fn run_level(state: &mut LevelState) -> io::Result<()> {
             // ))?
             .flush()?;
 
-        queue_print_level(&mut stdout, display_queue.peek().unwrap_or(state))?
+        queue_print_level(&mut stdout, display_queue.peek().unwrap_or(&state.level_state))?
             .queue(cursor::Hide)?
             //.queue(PrintStyledContent(String::from("HAHA").dark_blue()))?
             .queue(MoveToNextLine(1))?
+            .queue(Clear(ClearType::CurrentLine))?
             .queue(cursor::Hide)?
             .flush()?;
         // print!("{}", ansi_escapes::EraseLines());
         // println!("{}", state.to_unicode_string());
-        println!("{tick}");
+        println!(
+            "{} {} {} {tick}",
+            state.burrow_name(),
+            state.depth(),
+            inventory_string(&state.level_state, selected)
+        );
+        stdout
+            .queue(cursor::MoveToColumn(0))?
+            .queue(Clear(ClearType::CurrentLine))?;
+        if !message.is_empty() {
+            stdout.queue(Print(&message))?;
+        } else if deadlock::find_escaped(&state.level_state).is_some() {
+            // buns chased into other levels can still be caught there, and buns out of reach
+            // here may be reached coming in from another level, so only buns leaving the world
+            // are lost; the escaped buns left in the level are those
+            stdout.queue(Print("This level is now unsolvable, undo with z?"))?;
+        }
+        stdout.flush()?;
         tick += 1;
 
         loop {
@@ -87,19 +125,74 @@ pub(crate) fn run_level(state: &mut LevelState) -> io::Result<()> {
                     modifiers: KeyModifiers::CONTROL,
                     ..
                 }) => abort = true,
+                Event::Key(KeyEvent {
+                    kind: KeyEventKind::Press,
+                    code: KeyCode::Char(c @ ('s' | 'o')),
+                    modifiers: KeyModifiers::CONTROL,
+                    ..
+                }) => {
+                    message = if c == 's' {
+                        match state.save(save_path) {
+                            Ok(()) => format!("Saved to {}", save_path.display()),
+                            Err(e) => format!("Saving failed: {e:#}"),
+                        }
+                    } else {
+                        match state.world().load_state(save_path) {
+                            Ok(loaded) => {
+                                undo_stack.push(std::mem::replace(state, loaded));
+                                redo_stack.clear();
+                                display_queue = Vec::new().into_iter().peekable();
+                                format!("Loaded {}", save_path.display())
+                            }
+                            Err(e) => format!("Loading failed: {e:#}"),
+                        }
+                    };
+                }
+                Event::Key(KeyEvent {
+                    kind: KeyEventKind::Press,
+                    code: KeyCode::Char('R'),
+                    ..
+                }) => {
+                    undo_stack.push(state.clone());
+                    redo_stack.clear();
+                    state.restart_level();
+                    display_queue = Vec::new().into_iter().peekable();
+                }
                 Event::Key(KeyEvent {
                     code: c,
                     modifiers: KeyModifiers::NONE,
                     kind: KeyEventKind::Press,
                     ..
                 }) => {
-                    if let Ok::<Direction, _>(dir) = c.try_into() {
+                    if let KeyCode::Char('z' | 'u') = c {
+                        if let Some(previous) = undo_stack.pop() {
+                            redo_stack.push(std::mem::replace(state, previous));
+                            display_queue = Vec::new().into_iter().peekable();
+                        }
+                    } else if let KeyCode::Char('y' | 'r') = c {
+                        if let Some(next) = redo_stack.pop() {
+                            undo_stack.push(std::mem::replace(state, next));
+                            display_queue = Vec::new().into_iter().peekable();
+                        }
+                    } else if let KeyCode::Char(n @ '1'..='4') = c {
+                        selected = Item::from_repr(n as usize - '1' as usize);
+                    } else if c == KeyCode::Esc {
+                        selected = None;
+                    } else if let Ok::<Direction, _>(dir) = c.try_into() {
                         if applied_input {
                             continue;
                         }
-                        if let Ok(res) = state.move_to(dir) {
+                        let action = match selected.take() {
+                            Some(item) => Action::Use(item, dir),
+                            None => Action::Move(dir),
+                        };
+                        let before = state.clone();
+                        if let Ok(res) = state.apply(action) {
+                            undo_stack.push(before);
+                            redo_stack.clear();
                             display_queue = res.into_iter().peekable();
                             next_animation_step = Instant::now() + duration_step;
+                            message.clear();
                             applied_input = true
                         };
                     }
@@ -114,9 +207,143 @@ pub(crate) fn run_level(state: &mut LevelState) -> io::Result<()> {
     Ok(())
 }
 
+/// Replay a solution starting from `state`.
+///
+/// Pause and resume with space, step back and forth with the left and right arrow keys
+/// (pausing the replay), change the speed with `+`/`-` and quit with `q` or `ctrl+c`.
+/// If a step can't be taken, the replay ends before it and tells why.
+pub(crate) fn run_replay(state: &LevelState, solution: &[Action]) -> io::Result<()> {
+    // the state after each action, with the animation frames leading to it
+    let mut steps = vec![(state.clone(), Vec::new())];
+    // the step of the solution that can't be taken, with why, so it isn't mistaken for the end
+    let mut failed = None;
+    for (i, &action) in solution.iter().enumerate() {
+        let mut next = steps.last().unwrap().0.clone();
+        match next.apply(action) {
+            Ok(frames) => steps.push((next, frames)),
+            Err(e) => {
+                failed = Some(format!("step {} {action:?} failed: {e}", i + 1));
+                break;
+            }
+        }
+    }
+
+    let (cols, rows) = size()?;
+    enable_raw_mode()?;
+    let delta_t = Duration::from_millis(50);
+    let mut stdout = stdout();
+    stdout
+        .queue(cursor::DisableBlinking)?
+        .queue(cursor::Hide)?
+        .queue(Clear(ClearType::All))?
+        .flush()?;
+
+    let mut display_queue: std::iter::Peekable<IntoIter<LevelState>> =
+        Vec::new().into_iter().peekable();
+    let mut duration_step = Duration::from_millis(100);
+    let mut next_animation_step = Instant::now();
+    let mut current = 0;
+    let mut paused = false;
+    let mut abort = false;
+
+    while !abort {
+        let frame_start = Instant::now();
+        let next_frame = frame_start + delta_t;
+        if frame_start > next_animation_step {
+            if display_queue.peek().is_some() {
+                display_queue.next();
+                next_animation_step = frame_start + duration_step;
+            } else if !paused && current + 1 < steps.len() {
+                current += 1;
+                display_queue = steps[current].1.clone().into_iter().peekable();
+                // linger on finished moves a bit longer than on animation frames
+                next_animation_step = frame_start + duration_step * 4;
+            }
+        }
+
+        stdout.queue(MoveTo(1, 1))?.flush()?;
+        queue_print_level(
+            &mut stdout,
+            display_queue.peek().unwrap_or(&steps[current].0),
+        )?
+        .queue(MoveToNextLine(1))?
+        .queue(Clear(ClearType::CurrentLine))?
+        .flush()?;
+        let last_action = current.checked_sub(1).map(|i| solution[i]);
+        println!(
+            "step {current}/{} {last_action:?} {}ms{}",
+            steps.len() - 1,
+            duration_step.as_millis(),
+            if paused { " paused" } else { "" }
+        );
+        stdout
+            .queue(cursor::MoveToColumn(0))?
+            .queue(Clear(ClearType::CurrentLine))?;
+        if let Some(failed) = &failed {
+            stdout.queue(Print(failed))?;
+        }
+        stdout.flush()?;
+
+        while !abort && event::poll(next_frame.saturating_duration_since(Instant::now()))? {
+            let Event::Key(KeyEvent {
+                code,
+                modifiers,
+                kind: KeyEventKind::Press,
+                ..
+            }) = event::read()?
+            else {
+                continue;
+            };
+            match code {
+                KeyCode::Char('c') if modifiers == KeyModifiers::CONTROL => abort = true,
+                KeyCode::Char('q') => abort = true,
+                KeyCode::Char(' ') => paused ^= true,
+                KeyCode::Right if current + 1 < steps.len() => {
+                    paused = true;
+                    current += 1;
+                    display_queue = steps[current].1.clone().into_iter().peekable();
+                    next_animation_step = Instant::now() + duration_step;
+                }
+                KeyCode::Left if current > 0 => {
+                    paused = true;
+                    current -= 1;
+                    display_queue = Vec::new().into_iter().peekable();
+                }
+                KeyCode::Char('+') => {
+                    duration_step = (duration_step / 2).max(Duration::from_millis(10))
+                }
+                KeyCode::Char('-') => {
+                    duration_step = (duration_step * 2).min(Duration::from_secs(2))
+                }
+                _ => (),
+            }
+        }
+    }
 
-pub(crate) fn run_world(state: &mut WorldState) -> io::Result<()> {
-    run_level(&mut state.level_state)
+    io::stdout().queue(SetSize(cols, rows))?.flush()?;
+    disable_raw_mode()?;
+    Ok(())
+}
+
+/// Inventory counts, numbered by their selection key, with the selected item in brackets
+fn inventory_string(level: &LevelState, selected: Option<Item>) -> String {
+    let inventory = level.inventory();
+    Item::iter()
+        .map(|item| {
+            let entry = format!(
+                "{}:{}{}",
+                item as usize + 1,
+                item.to_unicode(),
+                inventory[item]
+            );
+            if selected == Some(item) {
+                format!("[{entry}]")
+            } else {
+                entry
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
 impl TryFrom<KeyCode> for Direction {
@@ -133,7 +360,19 @@ impl TryFrom<KeyCode> for Direction {
     }
 }
 
-pub fn queue_print_level<'a, W>(mut out: &'a mut W, level: &LevelState) -> io::Result<&'a mut W>
+pub fn queue_print_level<'a, W>(out: &'a mut W, level: &LevelState) -> io::Result<&'a mut W>
+where
+    W: QueueableCommand,
+{
+    queue_print_level_marked(out, level, None)
+}
+
+/// Like [`queue_print_level`], with the tile at `mark` standing out
+fn queue_print_level_marked<'a, W>(
+    mut out: &'a mut W,
+    level: &LevelState,
+    mark: Option<Position>,
+) -> io::Result<&'a mut W>
 where
     W: QueueableCommand,
 {
@@ -145,17 +384,21 @@ where
             out = out.queue(cursor::MoveTo(col, row + p.y() as u16))?;
             highlight = p.y() % 2 == 0;
         }
-        out = queue_tile(out, t, highlight)?;
+        out = if mark == Some(p) {
+            queue_tile_on(out, t, Color::DarkBlue)?
+        } else {
+            queue_tile(out, t, highlight)?
+        };
         highlight ^= true;
     }
     Ok(out)
 }
 
-pub fn queue_tile<W>(out: &mut W, (tile, item): TileContent, highlight: bool) -> io::Result<&mut W>
+pub fn queue_tile<W>(out: &mut W, t: TileContent, highlight: bool) -> io::Result<&mut W>
 where
     W: QueueableCommand,
 {
-    out.queue(SetBackgroundColor(if highlight {
+    let background = if highlight {
         Color::Rgb {
             r: 32,
             g: 32,
@@ -163,11 +406,22 @@ where
         }
     } else {
         Color::Black
-    }))?;
+    };
+    queue_tile_on(out, t, background)
+}
+
+fn queue_tile_on<W>(out: &mut W, (tile, item): TileContent, background: Color) -> io::Result<&mut W>
+where
+    W: QueueableCommand,
+{
+    out.queue(SetBackgroundColor(background))?;
     //out.queue(SetForegroundColor(if highlight {Color::Rgb { r: 245, g: 245, b: 245 }} else {Color::White}))?;
     match item {
         None => match tile {
             GroundTile::Hole => out.queue(Print("🕳   "))?.queue(MoveLeft(1)),
+            GroundTile::Wall { tunnels, .. } if tunnels.contains(&true) => {
+                out.queue(Print(format!("▓{}▓", tunnel_glyph(tunnels))))
+            }
             GroundTile::Wall {
                 breakable: true, ..
             } => out.queue(Print("░░░")),
@@ -180,5 +434,38 @@ where
         Some(TileItem::Paquerette) => out.queue(Print("👧 ")),
         Some(TileItem::Bun) => out.queue(Print("🐰 ")),
         Some(TileItem::Bunstack) => out.queue(Print("🗼  ")),
+        Some(TileItem::Tool(item)) => out.queue(Print(format!(" {} ", item.to_unicode()))),
+        Some(TileItem::Placed(item)) => out.queue(Print(format!("[{}]", item.to_unicode()))),
+    }
+}
+
+/// Print `level` with the tiles of `analysis` colored: green where Paquerette can walk,
+/// yellow where buns can be chased to and cyan where both can get to
+pub(crate) fn print_analysis(level: &LevelState, analysis: &Analysis) -> io::Result<()> {
+    let mut out = stdout();
+    for (p, t) in level.content() {
+        if p.x() == 0 && p.y() > 0 {
+            out.queue(ResetColor)?.queue(Print("\n"))?;
+        }
+        let paquerette = analysis.paquerette.get(p);
+        let bun = analysis.buns.iter().any(|b| b.get(p));
+        let background = match (paquerette, bun) {
+            (true, true) => Color::DarkCyan,
+            (true, false) => Color::DarkGreen,
+            (false, true) => Color::DarkYellow,
+            (false, false) => Color::Black,
+        };
+        queue_tile_on(&mut out, t, background)?;
     }
+    out.queue(ResetColor)?
+        .queue(Print("\n"))?
+        .queue(SetBackgroundColor(Color::DarkGreen))?
+        .queue(Print(" Paquerette "))?
+        .queue(SetBackgroundColor(Color::DarkYellow))?
+        .queue(Print(" buns "))?
+        .queue(SetBackgroundColor(Color::DarkCyan))?
+        .queue(Print(" both "))?
+        .queue(ResetColor)?
+        .queue(Print("\n"))?
+        .flush()
 }
@@ -0,0 +1,284 @@
+use std::io::{self, stdout, Write};
+
+use crossterm::{
+    cursor::{self, MoveTo, MoveToNextLine},
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    style::Print,
+    terminal::{disable_raw_mode, enable_raw_mode, size, Clear, ClearType, SetSize},
+    QueueableCommand,
+};
+use strum::EnumCount;
+
+use crate::data::{
+    grid::{Direction, GroundTile},
+    level_state::{ITEM_ATTRIBUTES, TUNNEL_ATTRIBUTES},
+    Item, LevelState, Position,
+};
+
+use super::{inventory_string, queue_print_level_marked};
+
+/// A change the editor makes to a tile
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Brush {
+    Ground(GroundTile),
+    /// toggle breakability of a wall, or put a breakable wall
+    Breakable,
+    /// toggle the tunnel opening of a wall in a direction
+    Tunnel(Direction),
+    /// move Paquerette and the entry of the level
+    Paquerette,
+    /// toggle a bun
+    Bun,
+    /// toggle an item lying on the floor
+    Item(Item),
+}
+
+impl Brush {
+    fn for_key(c: char) -> Option<Brush> {
+        let wall = GroundTile::Wall {
+            breakable: false,
+            tunnels: Default::default(),
+        };
+        let res = match c {
+            '#' => Brush::Ground(wall),
+            '%' => Brush::Breakable,
+            '.' | ' ' => Brush::Ground(GroundTile::Floor { is_entry: false }),
+            'o' => Brush::Ground(GroundTile::Hole),
+            'P' => Brush::Paquerette,
+            'b' => Brush::Bun,
+            _ => {
+                if let Some(&(_, d)) = TUNNEL_ATTRIBUTES.iter().find(|(a, _)| *a == c) {
+                    Brush::Tunnel(d)
+                } else {
+                    let &(_, item) = ITEM_ATTRIBUTES.iter().find(|(a, _)| *a == c)?;
+                    Brush::Item(item)
+                }
+            }
+        };
+        Some(res)
+    }
+}
+
+/// Apply `brush` to the tile at `p`, returning whether it fits there.
+///
+/// Buns and items only go on the floor, tunnels only into walls,
+/// and Paquerette has to be moved away before her tile can be covered.
+fn paint(state: &mut LevelState, p: Position, brush: Brush) -> bool {
+    let Some(tile) = state.tile_at(p) else {
+        return false;
+    };
+    let is_floor = matches!(tile, GroundTile::Floor { .. });
+    let is_paquerette = state.get_paquerette() == p;
+    match brush {
+        Brush::Ground(GroundTile::Floor { .. }) => {
+            state.set_tile_at(
+                p,
+                GroundTile::Floor {
+                    is_entry: is_paquerette,
+                },
+            );
+        }
+        Brush::Ground(_) | Brush::Breakable if is_paquerette => return false,
+        Brush::Ground(ground) => {
+            clear(state, p);
+            state.set_tile_at(p, ground);
+        }
+        Brush::Breakable => match tile {
+            GroundTile::Wall { breakable, tunnels } => state.set_tile_at(
+                p,
+                GroundTile::Wall {
+                    breakable: !breakable,
+                    tunnels,
+                },
+            ),
+            _ => {
+                clear(state, p);
+                state.set_tile_at(
+                    p,
+                    GroundTile::Wall {
+                        breakable: true,
+                        tunnels: Default::default(),
+                    },
+                );
+            }
+        },
+        Brush::Tunnel(d) => {
+            let GroundTile::Wall {
+                breakable,
+                mut tunnels,
+            } = tile
+            else {
+                return false;
+            };
+            tunnels[d] ^= true;
+            state.set_tile_at(p, GroundTile::Wall { breakable, tunnels });
+        }
+        _ if !is_floor => return false,
+        Brush::Paquerette => {
+            let old = state.get_paquerette();
+            if state.tile_at(old) == Some(GroundTile::Floor { is_entry: true }) {
+                state.set_tile_at(old, GroundTile::Floor { is_entry: false });
+            }
+            state.remove_bunny(p);
+            state.set_paquerette(p);
+            state.set_tile_at(p, GroundTile::Floor { is_entry: true });
+        }
+        Brush::Bun if is_paquerette => return false,
+        Brush::Bun => {
+            if !state.remove_bunny(p) {
+                state.set_bunny(p);
+            }
+        }
+        Brush::Item(item) => {
+            if state.remove_item_at(p) != Some(item) {
+                state.insert_item(p, item);
+            }
+        }
+    }
+    true
+}
+
+/// Take buns and items off the tile at `p`
+fn clear(state: &mut LevelState, p: Position) {
+    state.remove_bunny(p);
+    state.remove_item_at(p);
+}
+
+/// Edit a level, saving it with `save`.
+///
+/// Move the cursor with the arrow keys and change the tile under it:
+/// `#` wall, `%` breakable wall (toggling breakability of walls), `.` or space floor, `o` hole,
+/// `P` Paquerette and the entry, `b` bun, `t`/`p`/`c`/`s` items lying on the floor,
+/// `u`/`l`/`d`/`r` tunnel openings of walls.
+/// Add tools with `1`-`4` and clear them with `0`, undo with `z`,
+/// save with `ctrl+s` and quit with `ctrl+c`.
+pub(crate) fn run_editor(
+    state: &mut LevelState,
+    name: &str,
+    mut save: impl FnMut(&LevelState) -> anyhow::Result<()>,
+) -> io::Result<()> {
+    let (cols, rows) = size()?;
+    enable_raw_mode()?;
+    let mut stdout = stdout();
+    stdout
+        .queue(cursor::DisableBlinking)?
+        .queue(cursor::Hide)?
+        .queue(Clear(ClearType::All))?
+        .flush()?;
+
+    // the tile being edited
+    let mut at = state.get_paquerette();
+    let mut undo_stack = Vec::new();
+    let mut message = String::new();
+    let mut abort = false;
+
+    while !abort {
+        stdout.queue(MoveTo(1, 1))?.flush()?;
+        queue_print_level_marked(&mut stdout, state, Some(at))?
+            .queue(MoveToNextLine(1))?
+            .queue(Clear(ClearType::CurrentLine))?
+            .flush()?;
+        println!(
+            "{name} {} row {}, column {}: {}",
+            inventory_string(state, None),
+            at.y() + 1,
+            at.x() + 1,
+            state.level_token(at)
+        );
+        stdout
+            .queue(cursor::MoveToColumn(0))?
+            .queue(Clear(ClearType::CurrentLine))?
+            .queue(Print(&message))?
+            .flush()?;
+
+        let Event::Key(KeyEvent {
+            code,
+            modifiers,
+            kind: KeyEventKind::Press,
+            ..
+        }) = event::read()?
+        else {
+            continue;
+        };
+        message.clear();
+        let before = state.clone();
+        match code {
+            KeyCode::Char('c') if modifiers == KeyModifiers::CONTROL => abort = true,
+            KeyCode::Char('s') if modifiers == KeyModifiers::CONTROL => {
+                message = match save(state) {
+                    Ok(()) => "Saved".to_owned(),
+                    Err(e) => format!("Saving failed: {e:#}"),
+                };
+            }
+            KeyCode::Char('z') => {
+                if let Some(previous) = undo_stack.pop() {
+                    *state = previous;
+                }
+                continue;
+            }
+            KeyCode::Char('0') => state.set_inventory([0; Item::COUNT]),
+            KeyCode::Char(n @ '1'..='4') => {
+                let mut tools = [0; Item::COUNT];
+                tools[n as usize - '1' as usize] = 1;
+                state.add_inventory(tools);
+            }
+            KeyCode::Char(c) => match Brush::for_key(c) {
+                Some(brush) if !paint(state, at, brush) => {
+                    message = format!("Can't put {brush:?} here");
+                }
+                Some(_) => (),
+                None => message = format!("Unknown key {c}"),
+            },
+            _ => {
+                if let Ok::<Direction, _>(d) = code.try_into() {
+                    let next = at + d;
+                    if next.is_inner() {
+                        at = next;
+                    }
+                }
+            }
+        }
+        if *state != before {
+            undo_stack.push(before);
+        }
+    }
+
+    io::stdout().queue(SetSize(cols, rows))?.flush()?;
+    disable_raw_mode()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::{
+        grid::{Direction, GroundTile},
+        levels::walled,
+        LevelState, Position,
+    };
+
+    use super::{paint, Brush};
+
+    #[test]
+    fn test_paint_and_save() {
+        let mut state = walled();
+        let p = |x, y| Position::try_from((x, y)).unwrap();
+        assert!(paint(&mut state, p(3, 1), Brush::Bun));
+        assert!(paint(&mut state, p(5, 2), Brush::Breakable));
+        assert!(paint(&mut state, p(5, 2), Brush::Tunnel(Direction::Left)));
+        assert!(paint(&mut state, p(2, 2), Brush::Paquerette));
+        // Paquerette's tile can't be covered, and buns don't go into walls
+        assert!(!paint(&mut state, p(2, 2), Brush::Ground(GroundTile::Hole)));
+        assert!(!paint(&mut state, p(0, 0), Brush::Bun));
+        assert_eq!(
+            state.tile_at(p(1, 1)),
+            Some(GroundTile::Floor { is_entry: false })
+        );
+
+        let mut parsed = LevelState::new();
+        parsed
+            .parse_level(&state.to_level_string().unwrap())
+            .unwrap();
+        assert_eq!(parsed, state);
+        assert_eq!(state.level_token(p(5, 2)), "Rl");
+    }
+}